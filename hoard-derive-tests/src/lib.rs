@@ -1,7 +1,7 @@
 #![feature(never_type)]
 
 use leint::Le;
-use hoard_derive::Primitive;
+use hoard_derive::{Diff, Primitive};
 
 #[derive(Primitive)]
 #[repr(C)]
@@ -14,9 +14,36 @@ pub struct Outpoint {
 #[repr(C)]
 pub struct Foo(u8,bool);
 
-#[cfg(tests)]
+#[derive(Diff)]
+pub struct Pair {
+    a: u8,
+    b: Option<Le<u32>>,
+}
+
+#[derive(Diff)]
+pub enum Either {
+    Left(u8),
+    Right(Pair),
+}
+
+#[cfg(test)]
 mod tests {
+    use super::*;
+
+    use hoard::diff::{diff, Step};
+
     #[test]
     fn test() {
     }
+
+    #[test]
+    fn derive_diff() {
+        let old = Either::Right(Pair { a: 1, b: Some(2.into()) });
+        let new = Either::Right(Pair { a: 1, b: Some(3.into()) });
+        assert_eq!(diff(&old, &new, &()),
+                   &[vec![Step::Field(0), Step::Field(1), Step::Some]]);
+
+        assert_eq!(diff(&old, &Either::Left(1), &()), &[Vec::<Step>::new()]);
+        assert_eq!(diff(&old, &old, &()).len(), 0);
+    }
 }
@@ -0,0 +1,56 @@
+use quote::format_ident;
+
+use super::*;
+
+/// Derives `hoard::diff::Diff`.
+///
+/// Each field is compared in turn, under a `Step::Field` with its index. For enums, values of
+/// different variants are reported as changed without looking at their fields.
+pub fn derive_diff(mut s: synstructure::Structure) -> proc_macro2::TokenStream {
+    s.add_bounds(synstructure::AddBounds::None);
+
+    let mut new = s.clone();
+    new.binding_name(|_, i| format_ident!("__new_binding_{}", i));
+
+    let mut field_tys = vec![];
+    let arms = s.variants().iter().zip(new.variants()).map(|(old_vi, new_vi)| {
+        let old_pat = old_vi.pat();
+        let new_pat = new_vi.pat();
+
+        let fields = old_vi.bindings().iter().zip(new_vi.bindings()).enumerate().map(|(idx, (old_bi, new_bi))| {
+            field_tys.push(old_bi.ast().ty.clone());
+            quote! {
+                __path.push(::hoard::diff::Step::Field(#idx));
+                ::hoard::diff::Diff::diff_in(#old_bi, #new_bi, __zone, __path, __visitor);
+                __path.pop();
+            }
+        }).collect::<Vec<_>>();
+
+        quote! {
+            (#old_pat, #new_pat) => {
+                #( #fields )*
+            }
+        }
+    }).collect::<Vec<_>>();
+
+    for ty in field_tys {
+        s.add_where_predicate(syn::parse_quote!(#ty: ::hoard::diff::Diff<__Z>));
+    }
+
+    let r = s.gen_impl(quote! {
+        gen impl<__Z> ::hoard::diff::Diff<__Z> for @Self {
+            fn diff_in(&self, __new: &Self, __zone: &__Z,
+                       __path: &mut Vec<::hoard::diff::Step>,
+                       __visitor: &mut impl ::hoard::diff::DiffVisitor)
+            {
+                #[allow(unreachable_patterns)]
+                match (self, __new) {
+                    #( #arms )*
+                    _ => __visitor.changed(__path),
+                }
+            }
+        }
+    });
+    // eprintln!("{}", synstructure::unpretty_print(&r));
+    r
+}
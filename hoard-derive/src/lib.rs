@@ -4,6 +4,10 @@ use synstructure::decl_derive;
 
 decl_derive!([Primitive, attributes(foo)] => derive_primitive);
 
+mod diff;
+use self::diff::*;
+decl_derive!([Diff] => derive_diff);
+
 fn derive_primitive(s: synstructure::Structure) -> proc_macro2::TokenStream {
     let mut fields_ty = vec![];
     match &s.ast().data {
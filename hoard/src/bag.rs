@@ -93,10 +93,15 @@ impl<T: ?Sized + Pointee, P: Ptr, Z, M> From<Bag<T, P, Z, M>> for Own<T, P, M> {
 }
 
 #[derive(Debug, Error)]
-#[error("FIXME")]
-pub enum ValidateBagBlobError<OwnError: Error, ZoneError: Error> {
-    Own(OwnError),
-    Zone(ZoneError),
+pub enum ValidateBagBlobError<OwnError: Error + 'static, ZoneError: Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid bag pointer")]
+    Own(#[source] OwnError),
+
+    #[error("invalid bag zone")]
+    Zone(#[source] ZoneError),
 }
 
 impl<T: ?Sized + Pointee, P: Ptr, Z, M: 'static> ValidateBlob for Bag<T, P, Z, M>
@@ -109,9 +114,9 @@ where P: ValidateBlob,
     const BLOB_LEN: usize = <Own<T, P, M> as ValidateBlob>::BLOB_LEN + Z::BLOB_LEN;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        blob.field::<Own<T, P, M>>().map_err(ValidateBagBlobError::Own)?;
-        blob.field::<Z>().map_err(ValidateBagBlobError::Zone)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<Own<T, P, M>, _>(ValidateBagBlobError::Own)?;
+        blob.field::<Z, _>(ValidateBagBlobError::Zone)?;
+        unsafe { blob.finish() }
     }
 }
 
@@ -138,9 +143,9 @@ where P: Persist,
       M: Persist,
 {}
 
-impl<Q, R, T: ?Sized + Pointee, P: Ptr, Z> Encode<Q, R> for Bag<T, P, Z>
+impl<Q: Ptr, R, T: ?Sized + Pointee, P: Ptr, Z> Encode<Q, R> for Bag<T, P, Z>
 where R: Primitive,
-      T: Save<Q, R>,
+      T: Save<Q, R> + Load<Q>,
       Z: Encode<Q, R>,
       P: AsPtr<Q>,
 {
@@ -11,18 +11,15 @@ use super::*;
 unsafe impl<T: Persist, const N: usize> Persist for [T; N] {}
 
 #[derive(Error, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[error("array validation failed at index {idx}: {err}")]
-pub struct ValidateArrayError<E: Error, const N: usize> {
-    idx: usize,
-    err: E,
-}
-
-impl<E: Error, const N: usize> From<ValidateArrayError<E, N>> for !
-where E: Into<!>
-{
-    fn from(err: ValidateArrayError<E,N>) -> ! {
-        err.err.into()
-    }
+pub enum ValidateArrayError<E: Error, const N: usize> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("array validation failed at index {idx}: {err}")]
+    Element {
+        idx: usize,
+        err: E,
+    },
 }
 
 impl<T: ValidateBlob, const N: usize> ValidateBlob for [T; N] {
@@ -31,8 +28,8 @@ impl<T: ValidateBlob, const N: usize> ValidateBlob for [T; N] {
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
         for idx in 0 .. N {
-            blob.field::<T>().map_err(|err| ValidateArrayError { idx, err })?;
+            blob.field::<T, _>(|err| ValidateArrayError::Element { idx, err })?;
         }
-        unsafe { Ok(blob.finish()) }
+        unsafe { blob.finish() }
     }
 }
@@ -2,11 +2,16 @@ use super::*;
 
 use thiserror::Error;
 
-#[derive(Debug, Error)]
-#[error("FIXME")]
-pub enum ValidateBlobOptionError<E: std::error::Error> {
-    Discriminant,
-    Value(E),
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidateBlobOptionError<E: std::error::Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid option discriminant {0}")]
+    Discriminant(u8),
+
+    #[error("invalid option value")]
+    Value(#[source] E),
 }
 
 impl<T: ValidateBlob> ValidateBlob for Option<T> {
@@ -14,16 +19,16 @@ impl<T: ValidateBlob> ValidateBlob for Option<T> {
     type Error = ValidateBlobOptionError<T::Error>;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        match blob.field::<u8>().into_ok().as_value() {
+        match blob.field::<u8, Self::Error>(|never| never)?.as_value() {
             1 => {
-                blob.field::<T>().map_err(ValidateBlobOptionError::Value)?;
-                unsafe { Ok(blob.finish()) }
+                blob.field::<T, _>(ValidateBlobOptionError::Value)?;
+                unsafe { blob.finish() }
             },
             0 => {
-                blob.field_bytes(T::BLOB_LEN);
-                unsafe { Ok(blob.finish()) }
+                blob.padding(T::BLOB_LEN)?;
+                unsafe { blob.finish() }
             },
-            x => Err(ValidateBlobOptionError::Discriminant),
+            x => Err(ValidateBlobOptionError::Discriminant(*x)),
         }
     }
 }
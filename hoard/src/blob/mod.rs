@@ -6,6 +6,8 @@ use std::ops::{Deref, DerefMut};
 use std::fmt;
 use std::slice;
 
+use thiserror::Error;
+
 use crate::pointee::Pointee;
 
 pub mod impls;
@@ -33,7 +35,7 @@ impl<'a, T: ?Sized + Pointee> Borrow<Blob<'a, T>> for ValidBlob<'a, T> {
 
 pub trait ValidateBlob : Pointee {
     const BLOB_LEN: usize;
-    type Error : 'static + std::error::Error;
+    type Error : 'static + std::error::Error + Send + Sync;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error>;
 }
@@ -49,7 +51,7 @@ unsafe impl<T: ?Sized + ValidateBlob> BlobLen for T {
 }
 
 pub trait ValidateBlobPtr : BlobLen {
-    type Error : 'static + std::error::Error;
+    type Error : 'static + std::error::Error + Send + Sync;
 
     fn validate_blob_ptr<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error>;
 }
@@ -65,6 +67,15 @@ impl<T: ?Sized + ValidateBlob> ValidateBlobPtr for T {
 
 pub struct BlobValidator<'a, T: ?Sized + BlobLen> {
     cursor: BlobCursor<'a, T>,
+
+    /// Offset of this blob within the outermost blob being validated.
+    base: usize,
+
+    /// Whether non-canonical encodings are rejected.
+    canonical: bool,
+
+    /// Whether embedded piles are rejected.
+    pileless: bool,
 }
 
 pub unsafe trait Persist {
@@ -149,25 +160,88 @@ pub struct BlobCursor<'a, T: ?Sized + BlobLen, B = Blob<'a, T>> {
     idx: usize,
 }
 
+/// A structural error in a blob: its length, or how its fields were read.
+///
+/// Validation of well-formed input with correct `ValidateBlob` impls never hits these; they exist
+/// so that malformed input, or a buggy impl, fails validation rather than panicking.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlobError {
+    /// The blob isn't the length its type requires.
+    #[error("blob is {actual} bytes long; expected {expected}")]
+    Length {
+        expected: usize,
+        actual: usize,
+    },
+
+    /// A field extends past the end of the blob.
+    #[error("field of length {len} at {idx} runs past end of blob of length {blob_len}")]
+    OutOfRange {
+        idx: usize,
+        len: usize,
+        blob_len: usize,
+    },
+
+    /// Validation finished without reading every byte of the blob.
+    #[error("only {idx} of {blob_len} bytes of blob were read")]
+    Unfinished {
+        idx: usize,
+        blob_len: usize,
+    },
+
+    /// A pile embedded in a value that's being decoded without one, eg from a `ReadPile`.
+    #[error("value embeds a pile, which isn't available")]
+    EmbeddedPile,
+
+    /// Non-zero padding, found while validating in canonical mode.
+    ///
+    /// The range is relative to the start of the outermost blob being validated.
+    #[error("non-zero padding at bytes {start}..{end}")]
+    Padding {
+        start: usize,
+        end: usize,
+    },
+}
+
 impl<'a, T: ?Sized + BlobLen, B> BlobCursor<'a, T, B>
 where B: Borrow<Blob<'a, T>>
 {
+    pub fn try_field_blob<F: ValidateBlob>(&mut self) -> Result<Blob<'a, F>, BlobError> {
+        let buf = self.try_field_bytes(F::BLOB_LEN)?;
+        unsafe { Ok(Blob::new_unchecked(buf, F::make_sized_metadata())) }
+    }
+
     pub fn field_blob<F: ValidateBlob>(&mut self) -> Blob<'a, F> {
-        self.field_bytes(F::BLOB_LEN)
-            .try_into().unwrap()
+        self.try_field_blob()
+            .unwrap_or_else(|err| panic!("{}", err))
     }
 
-    pub fn field_bytes(&mut self, size: usize) -> &'a [u8] {
-        let r = self.blob.borrow()
-                    .as_bytes().get(self.idx .. self.idx + size)
-                               .expect("out of range");
+    pub fn try_field_bytes(&mut self, size: usize) -> Result<&'a [u8], BlobError> {
+        let buf = self.blob.borrow().as_bytes();
+        let r = self.idx.checked_add(size)
+                        .and_then(|end| buf.get(self.idx .. end))
+                        .ok_or(BlobError::OutOfRange { idx: self.idx, len: size, blob_len: buf.len() })?;
         self.idx += size;
-        r
+        Ok(r)
+    }
+
+    pub fn field_bytes(&mut self, size: usize) -> &'a [u8] {
+        self.try_field_bytes(size)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Finishes reading the blob, failing if any bytes are left unread.
+    pub fn try_finish(self) -> Result<B, BlobError> {
+        let blob_len = self.blob.borrow().as_bytes().len();
+        if self.idx == blob_len {
+            Ok(self.into_inner())
+        } else {
+            Err(BlobError::Unfinished { idx: self.idx, blob_len })
+        }
     }
 
     pub fn finish(self) -> B {
-        assert_eq!(self.idx, self.blob.borrow().as_bytes().len());
-        self.into_inner()
+        self.try_finish()
+            .unwrap_or_else(|err| panic!("{}", err))
     }
 
     pub fn into_inner(self) -> B {
@@ -210,6 +284,9 @@ impl<'a, T: ?Sized + BlobLen> From<Blob<'a, T>> for BlobValidator<'a, T> {
     fn from(blob: Blob<'a, T>) -> Self {
         Self {
             cursor: blob.into(),
+            base: 0,
+            canonical: false,
+            pileless: false,
         }
     }
 }
@@ -222,7 +299,7 @@ impl<'a, T: ?Sized + BlobLen> From<BlobValidator<'a, T>> for Blob<'a, T> {
 
 impl<'a, T: ?Sized + BlobLen> From<BlobCursor<'a, T>> for BlobValidator<'a, T> {
     fn from(cursor: BlobCursor<'a, T>) -> Self {
-        Self { cursor }
+        Self { cursor, base: 0, canonical: false, pileless: false }
     }
 }
 
@@ -241,14 +318,105 @@ impl<'a, T: ?Sized + BlobLen> DerefMut for BlobValidator<'a, T> {
 }
 
 impl<'a, T: ?Sized + BlobLen> BlobValidator<'a, T> {
-    pub fn field<U: ValidateBlob>(&mut self) -> Result<ValidBlob<'a, U>, U::Error> {
-        let buf = self.field_bytes(U::BLOB_LEN);
-        let blob = Blob::<U>::try_from(buf).unwrap();
-        U::validate_blob(blob.into())
+    /// Creates a validator that also rejects non-canonical encodings.
+    ///
+    /// Every value has exactly one canonical encoding; in particular, all padding is zero.
+    pub fn new_canonical(blob: Blob<'a, T>) -> Self {
+        Self {
+            cursor: blob.into(),
+            base: 0,
+            canonical: true,
+            pileless: false,
+        }
+    }
+
+    /// Creates a validator for a value that will be decoded without a pile to borrow from.
+    ///
+    /// Values that embed their pile, such as a `Bag`, are rejected with `BlobError::EmbeddedPile`.
+    pub fn new_pileless(blob: Blob<'a, T>) -> Self {
+        Self {
+            cursor: blob.into(),
+            base: 0,
+            canonical: false,
+            pileless: true,
+        }
     }
 
-    pub unsafe fn finish(self) -> ValidBlob<'a, T> {
-        self.cursor.finish()
-            .assume_valid()
+    /// Returns true if non-canonical encodings are rejected.
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// Returns true if embedded piles are rejected.
+    pub fn is_pileless(&self) -> bool {
+        self.pileless
+    }
+
+    /// Validates the next field, mapping its validation error with `f`.
+    pub fn field<U: ValidateBlob, E>(&mut self, f: impl FnOnce(U::Error) -> E) -> Result<ValidBlob<'a, U>, E>
+        where E: From<BlobError>
+    {
+        let base = self.base + self.cursor.idx;
+        let blob = self.try_field_blob::<U>()?;
+        U::validate_blob(BlobValidator {
+            cursor: blob.into(),
+            base,
+            canonical: self.canonical,
+            pileless: self.pileless,
+        }).map_err(f)
+    }
+
+    /// Skips `n` bytes of padding, which must be zero in canonical mode.
+    pub fn padding(&mut self, n: usize) -> Result<(), BlobError> {
+        let start = self.base + self.cursor.idx;
+        let buf = self.try_field_bytes(n)?;
+
+        if self.canonical {
+            if let Some(first) = buf.iter().position(|b| *b != 0) {
+                let last = buf.iter().rposition(|b| *b != 0).unwrap();
+                return Err(BlobError::Padding { start: start + first, end: start + last + 1 });
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes validation, failing if any bytes of the blob haven't been validated.
+    pub unsafe fn finish<E>(self) -> Result<ValidBlob<'a, T>, E>
+        where E: From<BlobError>
+    {
+        Ok(self.cursor.try_finish()?
+                      .assume_valid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads more, or less, than its blob length.
+    struct Buggy<const READ: usize>;
+
+    impl<const READ: usize> ValidateBlob for Buggy<READ> {
+        const BLOB_LEN: usize = 2;
+        type Error = BlobError;
+
+        fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
+            blob.try_field_bytes(READ)?;
+            unsafe { blob.finish() }
+        }
+    }
+
+    #[test]
+    fn buggy_validate_blob() {
+        let blob = Blob::<Buggy<3>>::try_from(&[0, 0][..]).unwrap();
+        assert_eq!(Buggy::<3>::validate_blob(blob.into()).unwrap_err(),
+                   BlobError::OutOfRange { idx: 0, len: 3, blob_len: 2 });
+
+        let blob = Blob::<Buggy<1>>::try_from(&[0, 0][..]).unwrap();
+        assert_eq!(Buggy::<1>::validate_blob(blob.into()).unwrap_err(),
+                   BlobError::Unfinished { idx: 1, blob_len: 2 });
+
+        let blob = Blob::<Buggy<2>>::try_from(&[0, 0][..]).unwrap();
+        assert!(Buggy::<2>::validate_blob(blob.into()).is_ok());
     }
 }
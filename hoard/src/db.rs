@@ -0,0 +1,352 @@
+//! Transactional databases, stored in a journal.
+//!
+//! A `Db` holds a single root value, which is read and written in transactions:
+//!
+//! ```no_run
+//! # use hoard::db::{Db, DbError, DbHeader};
+//! # use hoard::Le;
+//! const COUNTER: DbHeader = *b"example counter, version 1\0\0\0\0\0\0";
+//!
+//! let count = Db::open::<Le<u64>, _, _>("counter.db", COUNTER, |mut db| -> Result<_, DbError> {
+//!     db.write(|counter: &mut Le<u64>, _| -> Result<(), DbError> {
+//!         *counter = Le::new(counter.get() + 1);
+//!         Ok(())
+//!     })?;
+//!
+//!     db.read(|counter: &Le<u64>, _| counter.get())
+//! })?;
+//! # Ok::<(), DbError>(())
+//! ```
+//!
+//! The root type is identified by a tag chosen by the application, which is stored in the journal
+//! header and checked whenever the database is opened.
+//!
+//! Roots containing pointers are `OffsetMut<'p, 'v>`'s into the journal. `'p` brands the database
+//! itself: `open()` picks a unique `'p` for each database, so pointers from one can't be used with
+//! the pile of another. That's why a `Db` is only available within a closure, rather than returned
+//! by `open()`; it can't outlive the lifetime it was branded with. `'v` is the version read by a transaction, and only lasts as long as it
+//! does. A type alias with both as parameters is the easiest way to name such a root:
+//!
+//! ```
+//! # use hoard::offset::OffsetMut;
+//! # use hoard::ptr::Own;
+//! type Accounts<'p, 'v> = [Option<Own<u64, OffsetMut<'p, 'v>>>; 8];
+//! ```
+
+use std::any::{type_name, TypeId};
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use singlelife::Unique;
+use thiserror::Error;
+
+use crate::blob::ValidateBlob;
+use crate::journal::{Journal, JournalMut};
+use crate::journal::error::{JournalError, RootError};
+use crate::load::Load;
+use crate::offset::{Offset, OffsetMut};
+use crate::pile::Pile;
+use crate::pile::validate::type_key;
+use crate::ptr::{Ptr, Get, GetMut};
+use crate::refs::Ref;
+use crate::save::{Save, SaveBlob, SavePoll, SavePtr};
+
+/// The journal header of a database: a tag identifying its root type.
+///
+/// The tag is chosen by the application, and should change whenever the encoding of the root type
+/// does.
+pub type DbHeader = [u8; 32];
+
+/// Encodes a root's blob, or returns `None` if anything reachable from it is dirty.
+fn encode_clean<'p, 'v, T>(root: &T) -> Option<Vec<u8>>
+    where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+{
+    let mut poll = root.init_save(&CleanCheck(PhantomData));
+    poll.save_poll(CleanCheck(PhantomData)).ok()?;
+    Some(poll.save_blob(vec![]).into_ok())
+}
+
+/// Fails as soon as a dirty pointer needs saving.
+struct CleanCheck<'p, 'v>(PhantomData<OffsetMut<'p, 'v>>);
+
+impl<'p, 'v> SavePtr for CleanCheck<'p, 'v> {
+    type Source = OffsetMut<'p, 'v>;
+    type Target = Offset<'static, 'static>;
+    type Error = ();
+
+    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'a, T>>
+        where T: Load<Self::Source>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Err(Ref::Ref(r)),
+            Err(offset) => Ok(offset.to_static()),
+        }
+    }
+
+    fn try_save_ptr(self, _: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        Err(())
+    }
+}
+
+/// An error reading or writing a database.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DbError {
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Root(#[from] RootError),
+
+    #[error("database has root tag {found:x?}, expected {expected:x?}")]
+    Tag {
+        expected: DbHeader,
+        found: DbHeader,
+    },
+
+    #[error("database was opened with a different root type than {0}")]
+    RootType(&'static str),
+}
+
+/// A database, branded with the lifetime `'p`.
+#[derive(Debug)]
+pub struct Db<'p> {
+    journal: JournalMut<'p, DbHeader>,
+
+    /// Shares the journal's mapping; refreshed after every commit.
+    snapshot: Journal<'p, DbHeader>,
+
+    /// The root type the database was opened with.
+    root_type: TypeId,
+}
+
+impl Db<'_> {
+    /// Opens the database at `path`, holding roots of type `T` tagged with `tag`, and calls `f`
+    /// with it.
+    ///
+    /// The database is created if it doesn't exist, and locked until `f` returns. Fails if it
+    /// was created with a different tag.
+    pub fn open<T: ?Sized, R, E>(
+        path: impl AsRef<Path>,
+        tag: DbHeader,
+        f: impl for<'p> FnOnce(Db<'p>) -> Result<R, E>,
+    ) -> Result<R, E>
+        where E: From<DbError>
+    {
+        Unique::new(path, |path| {
+            f(Db::open_unique::<T>(path, tag)?)
+        })
+    }
+}
+
+impl<'p> Db<'p> {
+    /// Like `open()`, but branded by the unique lifetime of `path`.
+    pub fn open_unique<T: ?Sized>(path: Unique<'p, impl AsRef<Path>>, tag: DbHeader) -> Result<Self, DbError> {
+        let path = Unique::into_inner(path);
+        let path = path.as_ref();
+
+        // Safe to brand the journal with 'p, as the path it was opened from was unique.
+        let journal = match JournalMut::create(path, tag) {
+            Err(JournalError::Io(err)) if err.kind() == io::ErrorKind::AlreadyExists => {
+                JournalMut::open(path, true)?
+            },
+            r => r?,
+        };
+
+        let snapshot = journal.snapshot();
+        if *snapshot.header() != tag {
+            return Err(DbError::Tag { expected: tag, found: *snapshot.header() });
+        }
+        Ok(Self { journal, snapshot, root_type: type_key::<T>() })
+    }
+
+    /// Returns the underlying journal.
+    pub fn into_journal(self) -> JournalMut<'p, DbHeader> {
+        self.journal
+    }
+
+    fn check_root_type<T: ?Sized>(&self) -> Result<(), DbError> {
+        if self.root_type == type_key::<T>() {
+            Ok(())
+        } else {
+            Err(DbError::RootType(type_name::<T>()))
+        }
+    }
+
+    /// Reads the database.
+    ///
+    /// `f` is called with the latest root, and the pile that pointers within it point into.
+    pub fn read<'v, T, R>(&'v self, f: impl FnOnce(&T, &Pile<'p, 'v>) -> R) -> Result<R, DbError>
+        where T: Default + ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.check_root_type::<T>()?;
+
+        let (own, pile) = match self.snapshot.latest_root()? {
+            Some(bag) => bag.into_parts(),
+            None => (OffsetMut::alloc(T::default()), Pile::default()),
+        };
+
+        let root = own.get_in(&pile);
+        Ok(f(&root, &pile))
+    }
+
+    /// Writes to the database.
+    ///
+    /// `f` is called with a mutable copy of the latest root. If it returns `Ok`, the changes are
+    /// committed; if it returns an error, they're discarded and the database is left unchanged.
+    /// Nothing is committed if the root is left as it was.
+    pub fn write<'v, T, R, E>(&'v mut self, f: impl FnOnce(&mut T, &Pile<'p, 'v>) -> Result<R, E>)
+        -> Result<R, E>
+        where T: Default + ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>,
+              E: From<DbError>
+    {
+        self.check_root_type::<T>()?;
+
+        let Self { journal, snapshot, .. } = self;
+        let snapshot: &'v Journal<'p, DbHeader> = snapshot;
+
+        let (mut own, pile) = match snapshot.latest_root().map_err(DbError::Root)? {
+            Some(bag) => bag.into_parts(),
+            None => (OffsetMut::alloc(T::default()), Pile::default()),
+        };
+
+        let root = own.get_mut_in(&pile);
+        let before = encode_clean(&*root);
+
+        let r = f(root, &pile)?;
+
+        if before.is_none() || encode_clean(&*root) != before {
+            journal.write_root(root).map_err(DbError::Io)?;
+            snapshot.refresh().map_err(DbError::Journal)?;
+        }
+        Ok(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::Le;
+    use crate::ptr::Own;
+
+    type Tree<'p, 'v> = [Option<Own<Le<u64>, OffsetMut<'p, 'v>>>; 4];
+
+    const TREE: DbHeader = *b"hoard::db::tests::Tree\0\0\0\0\0\0\0\0\0\0";
+    const NUMBER: DbHeader = *b"hoard::db::tests::Number\0\0\0\0\0\0\0\0";
+
+    #[test]
+    fn read_write() -> Result<(), DbError> {
+        let dir = tempdir()?;
+        let path = dir.path().join("db");
+
+        Db::open::<Tree, _, _>(&path, TREE, |mut db| -> Result<_, DbError> {
+            assert!(db.read(|leaves: &Tree, _| leaves.iter().all(Option::is_none))?);
+
+            for i in 0 .. 4u64 {
+                db.write(|leaves: &mut Tree, pile| -> Result<_, DbError> {
+                    leaves[i as usize] = Some(OffsetMut::alloc(Le::new(i * 10)));
+                    if let Some(leaf) = &mut leaves[0] {
+                        let leaf = leaf.get_mut_in(pile);
+                        *leaf = Le::new(leaf.get() + 1);
+                    }
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+
+        let leaves = Db::open::<Tree, _, _>(&path, TREE, |db| {
+            db.read(|leaves: &Tree, pile| {
+                leaves.iter()
+                      .map(|leaf| leaf.as_ref().map(|leaf| leaf.get_in(pile).get()).unwrap())
+                      .collect::<Vec<u64>>()
+            })
+        })?;
+        assert_eq!(leaves, &[4, 10, 20, 30]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_abort() -> Result<(), DbError> {
+        let dir = tempdir()?;
+
+        Db::open::<u64, _, _>(dir.path().join("db"), NUMBER, |mut db| -> Result<_, DbError> {
+            db.write(|n: &mut u64, _| -> Result<(), DbError> {
+                *n = 1;
+                Ok(())
+            })?;
+
+            let r = db.write(|n: &mut u64, _| -> Result<(), DbError> {
+                *n = 2;
+                Err(io::Error::new(io::ErrorKind::Other, "abort").into())
+            });
+            assert!(matches!(r, Err(DbError::Io(_))));
+
+            assert_eq!(db.read(|n: &u64, _| *n)?, 1);
+            assert_eq!(db.into_journal().snapshot().marks().count(), 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn write_clean() -> Result<(), DbError> {
+        let dir = tempdir()?;
+
+        Db::open::<Tree, _, _>(dir.path().join("db"), TREE, |mut db| -> Result<_, DbError> {
+            db.write(|leaves: &mut Tree, _| -> Result<(), DbError> {
+                leaves[0] = Some(OffsetMut::alloc(Le::new(1)));
+                Ok(())
+            })?;
+
+            // Neither reading nor rewriting the same value makes the root dirty.
+            db.write(|leaves: &mut Tree, pile| -> Result<(), DbError> {
+                assert_eq!(leaves[0].as_ref().unwrap().get_in(pile).get(), 1);
+                leaves[1] = None;
+                Ok(())
+            })?;
+            assert_eq!(db.snapshot.marks().count(), 1);
+
+            db.write(|leaves: &mut Tree, pile| -> Result<(), DbError> {
+                let leaf = leaves[0].as_mut().unwrap().get_mut_in(pile);
+                *leaf = Le::new(2);
+                Ok(())
+            })?;
+            assert_eq!(db.snapshot.marks().count(), 2);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn open_errors() -> Result<(), DbError> {
+        let dir = tempdir()?;
+        let path = dir.path().join("db");
+
+        Db::open::<u8, _, DbError>(&path, NUMBER, |db| {
+            match Db::open::<u8, _, DbError>(&path, NUMBER, |_| Ok(())) {
+                Err(DbError::Journal(JournalError::Locked)) => {},
+                r => panic!("{:?}", r),
+            }
+
+            // The root type is checked against the one the database was opened with.
+            match db.read(|_: &u16, _| ()) {
+                Err(DbError::RootType("u16")) => {},
+                r => panic!("{:?}", r),
+            }
+            Ok(())
+        })?;
+
+        match Db::open::<u8, _, DbError>(&path, TREE, |_| Ok(())) {
+            Err(DbError::Tag { expected: TREE, found: NUMBER }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        Db::open::<u8, _, DbError>(&path, NUMBER, |_| Ok(()))
+    }
+}
@@ -0,0 +1,204 @@
+//! Structural diffs between two versions of a data structure.
+//!
+//! Copy-on-write means that parts of a structure left unchanged between two commits are still
+//! behind the same persistent pointer. `Diff` walks two values in parallel, skipping any pair of
+//! pointers that are equal without loading either side, and reports the path to every leaf that
+//! differs.
+//!
+//! Structs and enums of types that implement `Diff` can implement it with `#[derive(Diff)]` from
+//! `hoard-derive`.
+
+use std::num;
+
+use crate::Le;
+use crate::load::Load;
+use crate::pointee::Pointee;
+use crate::ptr::{Ptr, Own, Get};
+
+/// One step in the path from a root to a leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// The element of an array.
+    Index(usize),
+
+    /// A field of a struct or enum variant, by index; see `#[derive(Diff)]`.
+    Field(usize),
+
+    /// The value inside a `Some`.
+    Some,
+
+    /// The value behind a pointer.
+    Deref,
+}
+
+/// Receives the results of a diff.
+pub trait DiffVisitor {
+    /// Called for every leaf that differs, including an `Option` that's `None` on only one side.
+    fn changed(&mut self, path: &[Step]);
+
+    /// Called for every pair of pointers skipped because they're equal.
+    fn skipped(&mut self, path: &[Step]) {
+        let _ = path;
+    }
+}
+
+/// Collects the paths of changed leaves.
+impl DiffVisitor for Vec<Vec<Step>> {
+    fn changed(&mut self, path: &[Step]) {
+        self.push(path.to_vec());
+    }
+}
+
+/// Types that can be compared structurally, loading anything behind pointers from a zone `Z`.
+pub trait Diff<Z> {
+    /// Compares `self`, the old value, with `new`.
+    ///
+    /// `path` is the path to `self` from the root the diff started at; it's left as it was found.
+    fn diff_in(&self, new: &Self, zone: &Z, path: &mut Vec<Step>, visitor: &mut impl DiffVisitor);
+}
+
+/// Returns the paths of every leaf that differs between `old` and `new`.
+///
+/// Both must be loadable from `zone`. For roots from two commits of the same journal, that's the
+/// pile of the later commit, which contains everything in the earlier one.
+pub fn diff<T: ?Sized + Diff<Z>, Z>(old: &T, new: &T, zone: &Z) -> Vec<Vec<Step>> {
+    let mut changes = vec![];
+    old.diff_in(new, zone, &mut vec![], &mut changes);
+    changes
+}
+
+macro_rules! impl_diff_for_eq {
+    ($($t:ty,)+) => {$(
+        impl<Z> Diff<Z> for $t {
+            fn diff_in(&self, new: &Self, _: &Z, path: &mut Vec<Step>, visitor: &mut impl DiffVisitor) {
+                if self != new {
+                    visitor.changed(path);
+                }
+            }
+        }
+    )+}
+}
+
+impl_diff_for_eq! {
+    (), bool,
+    u8, u16, u32, u64, u128,
+    i8, i16, i32, i64, i128,
+    Le<u16>, Le<u32>, Le<u64>, Le<u128>,
+    Le<i16>, Le<i32>, Le<i64>, Le<i128>,
+    num::NonZeroU8, Le<num::NonZeroU16>, Le<num::NonZeroU32>, Le<num::NonZeroU64>, Le<num::NonZeroU128>,
+    num::NonZeroI8, Le<num::NonZeroI16>, Le<num::NonZeroI32>, Le<num::NonZeroI64>, Le<num::NonZeroI128>,
+}
+
+impl<Z, T: Diff<Z>> Diff<Z> for Option<T> {
+    fn diff_in(&self, new: &Self, zone: &Z, path: &mut Vec<Step>, visitor: &mut impl DiffVisitor) {
+        match (self, new) {
+            (None, None) => {},
+            (Some(old), Some(new)) => {
+                path.push(Step::Some);
+                old.diff_in(new, zone, path, visitor);
+                path.pop();
+            },
+            _ => visitor.changed(path),
+        }
+    }
+}
+
+impl<Z, T: Diff<Z>, const N: usize> Diff<Z> for [T; N] {
+    fn diff_in(&self, new: &Self, zone: &Z, path: &mut Vec<Step>, visitor: &mut impl DiffVisitor) {
+        for (i, (old, new)) in self.iter().zip(new.iter()).enumerate() {
+            path.push(Step::Index(i));
+            old.diff_in(new, zone, path, visitor);
+            path.pop();
+        }
+    }
+}
+
+impl<Z, T: ?Sized + Pointee, P: Ptr> Diff<Z> for Own<T, P>
+where Z: Get<P>,
+      T: Load<P> + Diff<Z>,
+      T::Metadata: PartialEq,
+      P::Persist: PartialEq,
+{
+    fn diff_in(&self, new: &Self, zone: &Z, path: &mut Vec<Step>, visitor: &mut impl DiffVisitor) {
+        // Dirty pointers are never equal: even if they point to the same value, something
+        // reachable from it may have changed.
+        if let (Err(old_ptr), Err(new_ptr)) = (self.try_get_dirty(), new.try_get_dirty()) {
+            if old_ptr == new_ptr && self.metadata == new.metadata {
+                visitor.skipped(path);
+                return;
+            }
+        }
+
+        let old = self.get_in(zone);
+        let new = new.get_in(zone);
+        path.push(Step::Deref);
+        old.diff_in(&new, zone, path, visitor);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempfile;
+
+    use crate::journal::JournalMut;
+    use crate::offset::OffsetMut;
+
+    #[derive(Default)]
+    struct Recorder {
+        changed: Vec<Vec<Step>>,
+        skipped: Vec<Vec<Step>>,
+    }
+
+    impl DiffVisitor for Recorder {
+        fn changed(&mut self, path: &[Step]) {
+            self.changed.push(path.to_vec());
+        }
+
+        fn skipped(&mut self, path: &[Step]) {
+            self.skipped.push(path.to_vec());
+        }
+    }
+
+    #[test]
+    fn diff_commits() -> std::io::Result<()> {
+        type Root<'p, 'v> = [Own<Option<Own<u8, OffsetMut<'p, 'v>>>, OffsetMut<'p, 'v>>; 3];
+
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        journal.write_root(&[OffsetMut::alloc(Some(OffsetMut::alloc(1u8))),
+                             OffsetMut::alloc(Some(OffsetMut::alloc(2u8))),
+                             OffsetMut::alloc(None)])?;
+
+        let snapshot = journal.snapshot();
+        let (own, pile) = snapshot.latest_root::<Root>().unwrap().unwrap().into_parts();
+        let mut root = own.take_in(&pile);
+
+        // Change a leaf, and an option, leaving the first element untouched.
+        let second = root[1].get_mut_in(&pile).as_mut().unwrap();
+        *second.get_mut_in(&pile) = 20;
+        *root[2].get_mut_in(&pile) = Some(OffsetMut::alloc(3u8));
+        journal.write_root(&root)?;
+        drop(root);
+
+        let snapshot = journal.snapshot();
+        let marks = snapshot.marks().collect::<Vec<_>>();
+        let (old, _) = snapshot.root_at::<Root>(marks[0]).unwrap().into_parts();
+        let (new, pile) = snapshot.root_at::<Root>(marks[1]).unwrap().into_parts();
+        let old = old.get_in(&pile);
+        let new = new.get_in(&pile);
+
+        let mut recorder = Recorder::default();
+        old.diff_in(&new, &pile, &mut vec![], &mut recorder);
+        assert_eq!(recorder.changed,
+                   &[vec![Step::Index(1), Step::Deref, Step::Some, Step::Deref],
+                     vec![Step::Index(2), Step::Deref]]);
+        assert_eq!(recorder.skipped,
+                   &[vec![Step::Index(0)]]);
+
+        assert_eq!(diff(&*old, &*new, &pile), recorder.changed);
+        assert_eq!(diff(&*new, &*new, &pile).len(), 0);
+        Ok(())
+    }
+}
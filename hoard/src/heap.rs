@@ -131,10 +131,10 @@ impl Ptr for HeapPtr {
 
 impl ValidateBlob for HeapPtr {
     const BLOB_LEN: usize = 0;
-    type Error = !;
+    type Error = BlobError;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        unsafe { Ok(blob.finish()) }
+        unsafe { blob.finish() }
     }
 }
 
@@ -146,10 +146,10 @@ impl Decode<HeapPtr> for HeapPtr {
 
 impl ValidateBlob for Heap {
     const BLOB_LEN: usize = 0;
-    type Error = !;
+    type Error = BlobError;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        unsafe { Ok(blob.finish()) }
+        unsafe { blob.finish() }
     }
 }
 
@@ -0,0 +1,472 @@
+//! Named roots, committed together under one mark.
+//!
+//! Catalogs live in journals created with the `catalog` flag set, where every commit is a
+//! catalog. The catalog is an ordinary saved value: a linked list of nodes, each holding a name
+//! and the offset and blob length of the root with that name. The roots themselves are saved
+//! first, as usual.
+//!
+//! As the types of the roots aren't recorded, `JournalMut::compact_roots()` can't copy a catalog;
+//! use `JournalMut::compact_catalogs()`, which says how to copy each root.
+
+use std::fs::File;
+use std::io;
+use std::str;
+
+use thiserror::Error;
+
+use crate::blob::{BlobError, BlobValidator, ValidBlob};
+use crate::load::{BlobDecoder, Decode};
+use crate::ptr::AsPtr;
+use crate::ptr::own::OwnEncoder;
+use crate::save::{Encode, EncodeBlob, WriteBlob};
+use crate::save::impls::option::OptionEncoder;
+
+use super::*;
+
+/// The maximum length in bytes of a root's name.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// The root of a catalog commit.
+pub(super) type CatalogRoot<'p, 'v> = Option<Own<Node<'p, 'v>, OffsetMut<'p, 'v>>>;
+
+/// One entry of a catalog, as saved.
+#[derive(Debug)]
+pub(super) struct Node<'p, 'v> {
+    name_len: u8,
+    name: [u8; MAX_NAME_LEN],
+    offset: Le<u64>,
+    blob_len: Le<u64>,
+    next: CatalogRoot<'p, 'v>,
+}
+
+#[derive(Debug, Error)]
+pub enum ValidateNodeError {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid catalog root name")]
+    Name,
+
+    #[error("invalid pointer to the next catalog entry")]
+    Next,
+}
+
+impl ValidateBlob for Node<'_, '_> {
+    const BLOB_LEN: usize = 1 + MAX_NAME_LEN + 8 + 8 + <CatalogRoot as ValidateBlob>::BLOB_LEN;
+    type Error = ValidateNodeError;
+
+    fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
+        let name_len = *blob.field::<u8, Self::Error>(|never| never)?.as_value() as usize;
+        let name = blob.field::<[u8; MAX_NAME_LEN], _>(|_| ValidateNodeError::Name)?.as_value();
+        if name_len > MAX_NAME_LEN
+            || name[name_len ..].iter().any(|b| *b != 0)
+            || str::from_utf8(&name[.. name_len]).is_err()
+        {
+            return Err(ValidateNodeError::Name);
+        }
+        blob.field::<Le<u64>, Self::Error>(|never| never)?;
+        blob.field::<Le<u64>, Self::Error>(|never| never)?;
+        blob.field::<CatalogRoot, _>(|_| ValidateNodeError::Next)?;
+        unsafe { blob.finish() }
+    }
+}
+
+impl<Q: Ptr> Decode<Q> for Node<'_, '_> {
+    fn decode_blob(mut blob: BlobDecoder<Q, Self>) -> Self {
+        let r = unsafe {
+            Self {
+                name_len: blob.field_unchecked(),
+                name: blob.field_unchecked(),
+                offset: blob.field_unchecked(),
+                blob_len: blob.field_unchecked(),
+                next: blob.field_unchecked(),
+            }
+        };
+        blob.finish();
+        r
+    }
+}
+
+/// Saves a `Node`.
+///
+/// The encoder of the rest of the list is boxed, as it contains another `NodeEncoder`.
+#[derive(Debug)]
+pub(super) struct NodeEncoder<Q, R> {
+    marker: PhantomData<fn() -> Q>,
+    name_len: u8,
+    name: [u8; MAX_NAME_LEN],
+    offset: Le<u64>,
+    blob_len: Le<u64>,
+    next: Box<OptionEncoder<OwnEncoder<NodeEncoder<Q, R>, (), R>>>,
+}
+
+impl<'p, 'v, Q: Ptr, R: Primitive> Encode<Q, R> for Node<'p, 'v>
+where OffsetMut<'p, 'v>: AsPtr<Q>
+{
+    type EncodePoll = NodeEncoder<Q, R>;
+
+    fn init_encode(&self, dst: &impl SavePtr<Source=Q, Target=R>) -> Self::EncodePoll {
+        NodeEncoder {
+            marker: PhantomData,
+            name_len: self.name_len,
+            name: self.name,
+            offset: self.offset,
+            blob_len: self.blob_len,
+            next: Box::new(self.next.init_encode(dst)),
+        }
+    }
+}
+
+impl<Q, R: Primitive> SavePoll<Q, R> for NodeEncoder<Q, R> {
+    fn save_poll<D: SavePtr<Source=Q, Target=R>>(&mut self, dst: D) -> Result<D, D::Error> {
+        self.next.save_poll(dst)
+    }
+}
+
+impl<Q, R: Primitive> EncodeBlob for NodeEncoder<Q, R> {
+    const BLOB_LEN: usize = 1 + MAX_NAME_LEN + 8 + 8
+                          + <OptionEncoder<OwnEncoder<Self, (), R>> as EncodeBlob>::BLOB_LEN;
+
+    fn encode_blob<W: WriteBlob>(&self, dst: W) -> Result<W::Done, W::Error> {
+        dst.write_primitive(&self.name_len)?
+           .write_primitive(&self.name)?
+           .write_primitive(&self.offset)?
+           .write_primitive(&self.blob_len)?
+           .write(&*self.next)?
+           .done()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    offset: usize,
+    blob_len: usize,
+}
+
+/// The named roots committed by one mark.
+#[derive(Debug, Clone)]
+pub struct Catalog<'p, 'v> {
+    marker: PhantomData<fn(&'p ()) -> &'p ()>,
+    buf: &'v [u8],
+    mark: usize,
+    entries: Vec<Entry>,
+}
+
+impl<'p, 'v> Catalog<'p, 'v> {
+    /// Reads the catalog from its root, committed by `mark` in `buf`.
+    pub(super) fn new(root: Bag<CatalogRoot<'p, 'v>, OffsetMut<'p, 'v>, Pile<'p, 'v>>,
+                      buf: &'v [u8], mark: usize) -> Self
+    {
+        fn collect<'p, 'v>(next: &CatalogRoot<'p, 'v>, pile: &Pile<'p, 'v>, entries: &mut Vec<Entry>) {
+            if let Some(own) = next {
+                let node = own.get_in(pile);
+                let name = &node.name[.. node.name_len as usize];
+                entries.push(Entry {
+                    name: str::from_utf8(name).expect("validated").to_owned(),
+                    offset: node.offset.get() as usize,
+                    blob_len: node.blob_len.get() as usize,
+                });
+                collect(&node.next, pile, entries);
+            }
+        }
+
+        let (own, pile) = root.into_parts();
+        let mut entries = vec![];
+        collect(&own.get_in(&pile), &pile, &mut entries);
+
+        Self {
+            marker: PhantomData,
+            buf,
+            mark,
+            entries,
+        }
+    }
+
+    /// Returns the names of the roots, in the order they were written.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    /// Returns the root called `name`, if there is one.
+    ///
+    /// The root's blob length is checked against `T`'s, and the root, and everything reachable
+    /// from it, is validated before the `Bag` is returned.
+    pub fn get<T>(&self, name: &str)
+        -> Result<Option<Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>>, CatalogError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        let entry = match self.entries.iter().find(|entry| entry.name == name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        if entry.blob_len != T::BLOB_LEN {
+            return Err(CatalogError::BlobLen {
+                name: entry.name.clone(),
+                expected: T::BLOB_LEN,
+                actual: entry.blob_len,
+            });
+        }
+
+        let offset = Offset::new_any(entry.offset).ok_or(CatalogError::Corrupt { mark: self.mark })?;
+        let pile = unsafe { TryPile::new_unchecked(self.buf) };
+        pile.validate_deep::<T>(offset)
+            .map_err(|err| CatalogError::Invalid { name: entry.name.clone(), err })?;
+
+        // Safe as everything reachable from the root was just validated.
+        let pile = unsafe { Pile::new_unchecked(self.buf) };
+        let own = unsafe { Own::new_unchecked(Fat::new(offset.into(), T::make_sized_metadata())) };
+        Ok(Some(Bag::from_parts(own, pile)))
+    }
+}
+
+/// Writes several named roots, then commits them under one mark.
+///
+/// Created by `JournalMut::catalog_writer()`. Nothing is committed until `commit()` is called.
+#[derive(Debug)]
+pub struct CatalogWriter<'a, 'p, H, F = File> {
+    writer: JournalWriter<'a, 'p, H, F>,
+    entries: Vec<Entry>,
+}
+
+impl<'a, 'p, H: Primitive, F: JournalFile> CatalogWriter<'a, 'p, H, F> {
+    pub(super) fn new(writer: JournalWriter<'a, 'p, H, F>) -> Self {
+        Self {
+            writer,
+            entries: vec![],
+        }
+    }
+
+    fn check_name(&self, name: &str) -> io::Result<()> {
+        if self.entries.iter().any(|entry| entry.name == name) {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               CatalogError::DuplicateName(name.to_owned())))
+        } else if name.len() > MAX_NAME_LEN {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               CatalogError::NameTooLong(name.to_owned())))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Saves a root, and everything dirty reachable from it, under `name`.
+    pub fn write_root<T>(self, name: &str, root: &T) -> io::Result<Self>
+        where T: ValidateBlob + Save<OffsetMut<'p, 'a>, Offset<'static, 'static>>,
+    {
+        self.check_name(name)?;
+        let Self { writer, mut entries } = self;
+
+        let mut poll = root.init_save(&writer);
+        let writer = poll.save_poll(writer)?;
+        let (writer, offset) = writer.try_save_ptr(&poll)?;
+
+        entries.push(Entry {
+            name: name.to_owned(),
+            offset: offset.get(),
+            blob_len: T::BLOB_LEN,
+        });
+        Ok(Self { writer, entries })
+    }
+
+    /// Copies the root called `name` from another catalog, and everything reachable from it.
+    ///
+    /// Unlike `write_root()`, clean pointers are loaded and written again rather than reused, so
+    /// this is how `JournalMut::compact_catalogs()` moves roots to the new journal.
+    pub fn copy_root<'v, T>(self, name: &str, from: &Catalog<'p, 'v>) -> io::Result<Self>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.check_name(name)?;
+        let (own, pile) = from.get::<T>(name)
+                              .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+                              .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,
+                                                            CatalogError::Missing(name.to_owned())))?
+                              .into_parts();
+        let Self { writer, mut entries } = self;
+
+        let compactor = Compactor { pile, writer };
+        let root = own.get_in(&pile);
+        let mut poll = root.init_save(&compactor);
+        let compactor = poll.save_poll(compactor)?;
+        let (compactor, offset) = compactor.try_save_ptr(&poll)?;
+
+        entries.push(Entry {
+            name: name.to_owned(),
+            offset: offset.get(),
+            blob_len: T::BLOB_LEN,
+        });
+        Ok(Self { writer: compactor.writer, entries })
+    }
+
+    /// Writes the catalog, and commits it along with every root written so far.
+    pub fn commit(self) -> io::Result<()> {
+        let Self { writer, entries } = self;
+
+        let mut root: CatalogRoot<'p, 'a> = None;
+        for entry in entries.into_iter().rev() {
+            let mut name = [0; MAX_NAME_LEN];
+            name[.. entry.name.len()].copy_from_slice(entry.name.as_bytes());
+            root = Some(OffsetMut::alloc(Node {
+                name_len: entry.name.len() as u8,
+                name,
+                offset: Le::new(entry.offset as u64),
+                blob_len: Le::new(entry.blob_len as u64),
+                next: root,
+            }));
+        }
+
+        let mut poll = root.init_save(&writer);
+        let mut writer = poll.save_poll(writer)?;
+        writer.write_blob(&poll)?;
+        writer.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempfile;
+
+    fn create() -> Result<JournalMut<'static, ()>, JournalError> {
+        let flags = JournalFlags { catalog: true, ..Default::default() };
+        JournalMut::create_from_fd_with_flags(tempfile()?, (), flags)
+    }
+
+    #[test]
+    fn catalog() -> Result<(), JournalError> {
+        let mut journal = create()?;
+
+        let snapshot = journal.snapshot();
+        assert!(snapshot.root::<u8>("utxos").unwrap().is_none());
+
+        journal.catalog_writer()?
+               .write_root("utxos", &OffsetMut::alloc(42u8))?
+               .write_root("headers", &Le::new(0x1234_5678_u32))?
+               .commit()?;
+
+        let snapshot = journal.snapshot();
+        let catalog = snapshot.latest_catalog().unwrap().unwrap();
+        assert_eq!(catalog.names().collect::<Vec<_>>(), &["utxos", "headers"]);
+
+        let (own, pile) = snapshot.root::<Own<u8, OffsetMut>>("utxos").unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile), 42);
+        assert_eq!(snapshot.root::<Le<u32>>("headers").unwrap().unwrap().get().get(), 0x1234_5678);
+        assert!(snapshot.root::<u8>("blocks").unwrap().is_none());
+
+        // Wrong type
+        assert!(snapshot.root::<u8>("headers").is_err());
+
+        // Right length, but the pointer doesn't point to a valid bool.
+        match snapshot.root::<Own<bool, OffsetMut>>("utxos") {
+            Err(CatalogError::Invalid { name, .. }) if name == "utxos" => {},
+            r => panic!("{:?}", r),
+        }
+
+        // Both roots advance together; the old catalog is still readable.
+        let first = snapshot.marks().last().unwrap();
+        journal.catalog_writer()?
+               .write_root("utxos", &OffsetMut::alloc(43u8))?
+               .write_root("headers", &Le::new(0x9abc_def0_u32))?
+               .commit()?;
+
+        let snapshot = journal.snapshot();
+        let (own, pile) = snapshot.root::<Own<u8, OffsetMut>>("utxos").unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile), 43);
+        assert_eq!(snapshot.root::<Le<u32>>("headers").unwrap().unwrap().get().get(), 0x9abc_def0);
+
+        let old = snapshot.catalog_at(first).unwrap();
+        assert_eq!(old.get::<Le<u32>>("headers").unwrap().unwrap().get().get(), 0x1234_5678);
+
+        // An empty catalog
+        journal.catalog_writer()?.commit()?;
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.latest_catalog().unwrap().unwrap().names().count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn catalog_errors() -> Result<(), JournalError> {
+        let mut journal = create()?;
+
+        let err = journal.catalog_writer()?
+                         .write_root("utxos", &1u8)?
+                         .write_root("utxos", &2u8)
+                         .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let err = journal.catalog_writer()?
+                         .write_root(&"x".repeat(MAX_NAME_LEN + 1), &1u8)
+                         .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Nothing was committed.
+        assert_eq!(journal.snapshot().marks().count(), 0);
+
+        // Only catalogs can be committed to a catalog journal...
+        assert_eq!(journal.write_root(&0u8).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // ...and only roots to any other, so a root can't be mistaken for a catalog.
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        assert_eq!(journal.catalog_writer().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        journal.write_root(&[0u8; 24])?;
+        let snapshot = journal.snapshot();
+        match snapshot.root::<u8>("utxos") {
+            Err(CatalogError::NotACatalog { mark: 3 }) => {},
+            r => panic!("{:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn catalog_compact() -> Result<(), JournalError> {
+        type Utxos<'p, 'v> = Own<Own<u8, OffsetMut<'p, 'v>>, OffsetMut<'p, 'v>>;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        let flags = JournalFlags { catalog: true, ..Default::default() };
+        let mut journal = JournalMut::create_with_flags(&path, (), flags)?;
+        for i in 0 .. 10u8 {
+            journal.catalog_writer()?
+                   .write_root("utxos", &OffsetMut::alloc(OffsetMut::alloc(i)))?
+                   .write_root("height", &Le::new(i as u32))?
+                   .commit()?;
+        }
+
+        // The catalog doesn't say what type each root is.
+        match journal.compact::<Utxos>(&path) {
+            Err(JournalError::CatalogFlag { expected: false }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        let marks = journal.snapshot().marks().collect::<Vec<_>>();
+        let journal = journal.compact_catalogs(&path, vec![marks[3], marks[9]], |catalog, writer| {
+            writer.copy_root::<Utxos>("utxos", catalog)?
+                  .copy_root::<Le<u32>>("height", catalog)
+        })?;
+
+        let snapshot = journal.snapshot();
+        let values = snapshot.marks().map(|mark| {
+            let catalog = snapshot.catalog_at(mark).unwrap();
+            let (own, pile) = catalog.get::<Utxos>("utxos").unwrap().unwrap().into_parts();
+            let utxos = *own.get_in(&pile).get_in(&pile).get_in(&pile);
+            (utxos, catalog.get::<Le<u32>>("height").unwrap().unwrap().get().get())
+        }).collect::<Vec<_>>();
+        assert_eq!(values, &[(3, 3), (9, 9)]);
+
+        // A root the catalog doesn't have.
+        let mark = snapshot.marks().last().unwrap();
+        let err = journal.compact_catalogs(&path, vec![mark], |catalog, writer| {
+            writer.copy_root::<u8>("blocks", catalog)
+        }).unwrap_err();
+        match err {
+            JournalError::Io(err) if err.kind() == io::ErrorKind::NotFound => {},
+            r => panic!("{:?}", r),
+        }
+        Ok(())
+    }
+}
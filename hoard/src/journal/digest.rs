@@ -0,0 +1,62 @@
+//! Per-commit integrity digests.
+
+use std::mem;
+
+use sha2::{Digest, Sha256};
+
+use super::wordoffset::Word;
+
+/// Length of the digest trailer written immediately before each mark.
+///
+/// The 32 byte SHA256 digest is spread over five words, seven bytes per word, leaving the high
+/// byte of every word zero. Marks are `!idx`, so their high byte is `0xff` for any journal shorter
+/// than 2^59 bytes, and a trailer word is never mistaken for a mark. Thus the trailer never needs
+/// conflict padding, and the root blob stays at a fixed distance from its mark.
+pub const TRAILER_LEN: usize = 5 * mem::size_of::<Word>();
+
+/// Hashes the bytes committed since the previous mark.
+pub fn hasher() -> Sha256 {
+    Sha256::new()
+}
+
+pub fn encode_trailer(hasher: Sha256) -> [u8; TRAILER_LEN] {
+    let digest = hasher.result();
+
+    let mut trailer = [0; TRAILER_LEN];
+    for (src, dst) in digest.chunks(mem::size_of::<Word>() - 1)
+                            .zip(trailer.chunks_mut(mem::size_of::<Word>()))
+    {
+        dst[.. src.len()].copy_from_slice(src);
+    }
+    trailer
+}
+
+/// Checks a commit's bytes against the trailer at its end.
+pub fn check(commit: &[u8]) -> bool {
+    match commit.len().checked_sub(TRAILER_LEN) {
+        Some(len) => {
+            let (bytes, trailer) = commit.split_at(len);
+            let mut hasher = hasher();
+            hasher.input(bytes);
+            encode_trailer(hasher)[..] == *trailer
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trailer_high_bytes() {
+        let mut hasher = hasher();
+        hasher.input(b"abc");
+        let trailer = encode_trailer(hasher);
+
+        for word in trailer.chunks(mem::size_of::<Word>()) {
+            assert_eq!(word[7], 0);
+        }
+        assert_eq!(&trailer[0 .. 7], &[0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::pile::validate::ValidateDeepError;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum JournalError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("journal is {len} bytes long, shorter than its {header_len} byte header")]
+    TooShort {
+        len: u64,
+        header_len: usize,
+    },
+
+    #[error("not a journal: bad magic")]
+    BadMagic,
+
+    #[error("unsupported journal format version {version}")]
+    UnsupportedVersion {
+        version: u64,
+    },
+
+    #[error("unsupported journal flags {flags:#x}")]
+    UnsupportedFlags {
+        flags: u64,
+    },
+
+    #[error("invalid journal header: {0}")]
+    Header(String),
+
+    #[error("invalid root at mark {mark}")]
+    Root {
+        mark: usize,
+        #[source]
+        err: RootError,
+    },
+
+    #[error("invalid catalog at mark {mark}")]
+    Catalog {
+        mark: usize,
+        #[source]
+        err: CatalogError,
+    },
+
+    #[error("operation needs a journal whose catalog flag is {expected}")]
+    CatalogFlag {
+        expected: bool,
+    },
+
+    #[error("journal is locked by another handle")]
+    Locked,
+
+    #[error("commit conflict: expected tip mark {expected:?}, found {actual:?}")]
+    Conflict {
+        expected: Option<usize>,
+        actual: Option<usize>,
+    },
+
+    #[error("journal has {actual} committed bytes after commit; expected {expected} bytes")]
+    Truncated {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl From<JournalError> for io::Error {
+    fn from(err: JournalError) -> io::Error {
+        match err {
+            JournalError::Io(err) => err,
+            err @ JournalError::Truncated { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err @ JournalError::CatalogFlag { .. } => io::Error::new(io::ErrorKind::InvalidInput, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("no commit mark at word {0}")]
+pub struct NotAMarkError(pub usize);
+
+/// An error getting the root committed by a mark.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RootError {
+    #[error(transparent)]
+    NotAMark(#[from] NotAMarkError),
+
+    #[error("root at mark {mark} failed validation")]
+    Invalid {
+        mark: usize,
+        #[source]
+        err: ValidateDeepError,
+    },
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CatalogError {
+    #[error("commit at mark {mark} has no catalog: the journal doesn't have the catalog flag")]
+    NotACatalog {
+        mark: usize,
+    },
+
+    #[error(transparent)]
+    Root(#[from] RootError),
+
+    #[error("catalog at mark {mark} is corrupt")]
+    Corrupt {
+        mark: usize,
+    },
+
+    #[error("catalog root {name:?} failed validation")]
+    Invalid {
+        name: String,
+        #[source]
+        err: ValidateDeepError,
+    },
+
+    #[error("catalog has no root {0:?}")]
+    Missing(String),
+
+    #[error("catalog root {name:?} is a {actual} byte blob, not {expected} bytes")]
+    BlobLen {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("catalog root {0:?} written twice")]
+    DuplicateName(String),
+
+    #[error("catalog root name {0:?} is longer than {max} bytes", max = crate::journal::catalog::MAX_NAME_LEN)]
+    NameTooLong(String),
+}
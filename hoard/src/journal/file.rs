@@ -0,0 +1,113 @@
+//! Files backing a writable journal, when they're synced, and how they're locked.
+
+use std::fs::File;
+use std::io::{self, Write, Seek};
+use std::time::Duration;
+
+use fs2::FileExt;
+
+use super::error::JournalError;
+
+/// The file a `JournalMut` writes to.
+///
+/// Implemented for `File`; other implementations wrap one, eg to observe the writes and syncs
+/// made by a commit.
+pub trait JournalFile : Write + Seek {
+    /// The underlying file, which is memory-mapped for reading.
+    fn file(&self) -> &File;
+
+    fn sync_data(&self) -> io::Result<()> {
+        self.file().sync_data()
+    }
+}
+
+impl JournalFile for File {
+    fn file(&self) -> &File {
+        self
+    }
+}
+
+/// When commits are synced to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Never sync; the OS writes data back whenever it likes.
+    None,
+
+    /// Sync every commit before `commit` returns.
+    ///
+    /// The committed bytes are synced before the mark is written, and the mark after, so a mark
+    /// never reaches the disk before the data it commits.
+    Always,
+
+    /// Sync the data of every commit before its mark, but only sync the marks themselves once
+    /// every `commits` commits, or on the first commit at least `interval` after the previous
+    /// sync, whichever comes first.
+    ///
+    /// Commits in between can be lost on power failure, though a mark never reaches the disk
+    /// without the data it commits. There's no background timer, so the last commits stay
+    /// unsynced until the next sync is due: callers must call `JournalMut::flush()` when they go
+    /// idle.
+    Group {
+        commits: usize,
+        interval: Duration,
+    },
+}
+
+/// Journals sync every commit by default.
+///
+/// Before durability was configurable they never synced at all; use `Durability::None` to get
+/// that behaviour back.
+impl Default for Durability {
+    fn default() -> Self {
+        Durability::Always
+    }
+}
+
+/// What to do when another handle holds a conflicting lock on a journal file.
+///
+/// Journals opened by path take an advisory lock: exclusive for a `JournalMut`, shared for a
+/// `Journal`. Journals opened from an existing file descriptor are not locked, as the caller may
+/// well already hold one on the same open file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lock {
+    /// Fail with `JournalError::Locked`.
+    FailFast,
+
+    /// Block until the lock is released.
+    Wait,
+
+    /// Don't lock at all, eg to follow a journal that another process has open for writing.
+    None,
+}
+
+impl Default for Lock {
+    fn default() -> Self {
+        Lock::FailFast
+    }
+}
+
+impl Lock {
+    pub(super) fn lock_shared(self, fd: &File) -> Result<(), JournalError> {
+        match self {
+            Lock::FailFast => FileExt::try_lock_shared(fd).map_err(Self::map_err),
+            Lock::Wait => Ok(FileExt::lock_shared(fd)?),
+            Lock::None => Ok(()),
+        }
+    }
+
+    pub(super) fn lock_exclusive(self, fd: &File) -> Result<(), JournalError> {
+        match self {
+            Lock::FailFast => FileExt::try_lock_exclusive(fd).map_err(Self::map_err),
+            Lock::Wait => Ok(FileExt::lock_exclusive(fd)?),
+            Lock::None => Ok(()),
+        }
+    }
+
+    fn map_err(err: io::Error) -> JournalError {
+        if err.kind() == fs2::lock_contended_error().kind() {
+            JournalError::Locked
+        } else {
+            err.into()
+        }
+    }
+}
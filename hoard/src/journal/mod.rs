@@ -1,176 +1,983 @@
 use std::cmp;
 use std::convert::{TryFrom, TryInto};
-use std::fs::{File, OpenOptions};
-use std::io::{self, Write, Seek, SeekFrom};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write, Seek, SeekFrom};
 use std::marker::PhantomData;
 use std::mem::{self, MaybeUninit};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::slice;
 use std::sync::Arc;
-
-use memmap::Mmap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
 
 use crate::Le;
+use crate::bag::Bag;
+use crate::blob::ValidateBlob;
 use crate::pointee::Pointee;
 use crate::offset::{OffsetMut, Offset};
-use crate::pile::TryPile;
-use crate::save::{self, SavePtr, SaveBlob, Save, SavePoll};
+use crate::pile::{Pile, TryPile, mapping::Mapping};
+use crate::primitive::Primitive;
+use crate::load::Load;
+use crate::ptr::{Ptr, Own, Fat, Get};
+use crate::refs::Ref;
+use crate::save::{self, SavePtr, SaveBlob, Save, SavePoll, Dedup};
 
 mod wordoffset;
 use self::wordoffset::{Word, WordOffset};
 
+pub mod error;
+use self::error::*;
+
+mod digest;
+
+pub mod file;
+pub use self::file::{JournalFile, Durability, Lock};
+
+pub mod catalog;
+pub use self::catalog::{Catalog, CatalogWriter};
+use self::catalog::CatalogRoot;
+
 #[derive(Debug)]
 pub struct Journal<'p, H = ()> {
     marker: PhantomData<fn(&'p ()) -> &'p H>,
-    mapping: Arc<Mmap>,
+    flags: JournalFlags,
+    header: Arc<H>,
+
+    /// Shared between clones, each of which has its own length.
+    mapping: Arc<Mapping>,
+    len: AtomicUsize,
+
+    /// The length up to and including the last intact mark.
+    committed_len: AtomicUsize,
+}
+
+/// Format options, fixed when a journal is created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JournalFlags {
+    /// Write a digest of the bytes committed since the previous mark before every mark.
+    pub digests: bool,
+
+    /// Every commit is a catalog of named roots, written with `JournalMut::catalog_writer()`.
+    pub catalog: bool,
+}
+
+impl JournalFlags {
+    const DIGESTS: u64 = 1;
+    const CATALOG: u64 = 2;
+
+    fn to_bits(self) -> u64 {
+        (if self.digests { Self::DIGESTS } else { 0 })
+            | (if self.catalog { Self::CATALOG } else { 0 })
+    }
+
+    fn from_bits(bits: u64) -> Option<Self> {
+        if bits & !(Self::DIGESTS | Self::CATALOG) == 0 {
+            Some(Self {
+                digests: bits & Self::DIGESTS != 0,
+                catalog: bits & Self::CATALOG != 0,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The result of checking a commit's digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitStatus {
+    Intact,
+    Corrupt,
+
+    /// The journal was created without digests.
+    Unchecked,
 }
 
 impl<H> Clone for Journal<'_, H> {
     fn clone(&self) -> Self {
         Self {
             marker: PhantomData,
+            flags: self.flags,
+            header: self.header.clone(),
             mapping: self.mapping.clone(),
+            len: AtomicUsize::new(self.len.load(Ordering::Acquire)),
+            committed_len: AtomicUsize::new(self.committed_len.load(Ordering::Acquire)),
         }
     }
 }
 
-impl<'p, H> Journal<'p, H> {
-    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+impl<'p, H: Primitive> Journal<'p, H> {
+    /// Opens a journal for reading, taking a shared lock on it.
+    ///
+    /// Fails with `JournalError::Locked` if a `JournalMut` has the file open. To follow a journal
+    /// while it's being written to, open it with `open_with_lock()` and `Lock::None` instead.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        Self::open_with_lock(path, Lock::default())
+    }
+
+    /// Opens a journal for reading, taking a shared lock on it unless `lock` is `Lock::None`.
+    ///
+    /// Writers are kept out for as long as the lock is held. Readers only ever see committed
+    /// bytes, and a writer never shortens the file, so with `Lock::None` it's safe to read while a
+    /// `JournalMut` has the file open; `refresh()` picks up its commits.
+    pub fn open_with_lock(path: impl AsRef<Path>, lock: Lock) -> Result<Self, JournalError> {
         let fd = OpenOptions::new()
                              .read(true)
                              .open(path)?;
+        lock.lock_shared(&fd)?;
+
+        // The mapping keeps a duplicate of fd, and with it the lock.
         Self::open_fd(&fd)
     }
 
-    pub fn open_fd(fd: &File) -> io::Result<Self> {
-        Ok(Self {
+    /// Opens a journal for reading.
+    ///
+    /// The magic, format version, and user header are checked. Any bytes after the last intact
+    /// mark are ignored; use `uncommitted_len()` to find out if there are any.
+    pub fn open_fd(fd: &File) -> Result<Self, JournalError> {
+        let mapping = Self::make_mapping(fd)?;
+        let (flags, header) = JournalHeader::decode(&mapping.as_bytes()[.. JournalHeader::<H>::LEN])?;
+
+        let this = Self {
             marker: PhantomData,
-            mapping: Self::make_mapping(fd)?,
-        })
+            flags,
+            header: Arc::new(header),
+            len: AtomicUsize::new(mapping.len()),
+            committed_len: AtomicUsize::new(JournalHeader::<H>::LEN),
+            mapping: Arc::new(mapping),
+        };
+        this.refresh_committed_len();
+        Ok(this)
     }
 
-    fn make_mapping(fd: &File) -> io::Result<Arc<Mmap>> {
-        let mapping = unsafe { Mmap::map(fd)? };
+    /// Returns the user header.
+    pub fn header(&self) -> &H {
+        &self.header
+    }
 
-        if mapping.len() < mem::size_of::<JournalHeader<H>>() {
-            todo!()
+    pub fn flags(&self) -> JournalFlags {
+        self.flags
+    }
+
+    /// Length of the trailer between each root and its mark.
+    fn trailer_len(&self) -> usize {
+        if self.flags.digests { digest::TRAILER_LEN } else { 0 }
+    }
+
+    fn make_mapping(fd: &File) -> Result<Mapping, JournalError> {
+        let header_len = JournalHeader::<H>::LEN;
+
+        let len = fd.metadata()?.len();
+        if len < header_len as u64 {
+            return Err(JournalError::TooShort { len, header_len });
+        }
+
+        let mapping = Mapping::new(fd)?;
+        if mapping.len() < header_len {
+            Err(JournalError::TooShort { len: mapping.len() as u64, header_len })
         } else {
-            Ok(mapping.into())
+            Ok(mapping)
         }
     }
 
+    /// Returns the length of the journal file, as of when it was opened or last refreshed.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
 
-    fn mapping_parts(&self) -> (&JournalHeader<H>, &[u8]) {
-        let (header, rest) = self.mapping.split_at(mem::size_of::<JournalHeader<H>>());
+    /// Picks up anything appended to the file since the journal was opened or last refreshed.
+    ///
+    /// Returns `true` if the journal grew. Roots already returned remain valid, and still see the
+    /// journal as it was when they were loaded. Clones made before the refresh are unaffected.
+    pub fn refresh(&self) -> Result<bool, JournalError> {
+        let new_len = self.mapping.refresh()?;
+        let old_len = self.len.swap(new_len, Ordering::AcqRel);
+        self.refresh_committed_len();
+        Ok(new_len > old_len)
+    }
 
-        let header = unsafe { &*(header.as_ptr() as *const JournalHeader<H>) };
-        (header, rest)
+    /// Finds the last intact mark, starting from the previous one.
+    fn refresh_committed_len(&self) {
+        let header_len = JournalHeader::<H>::LEN;
+        let start = self.committed_len.load(Ordering::Acquire) - header_len;
+        self.committed_len.store(header_len + self.intact_end(start), Ordering::Release);
+    }
+
+    /// Returns the end of the last intact commit, relative to the start of the body.
+    ///
+    /// Only marks after `start`, the end of a commit already known to be intact, are looked at.
+    /// Without digests every mark is intact. With them, a mark only counts if the bytes since the
+    /// previous mark match its trailer, so a mark that reached the disk without all the bytes it
+    /// commits is skipped. A damaged commit further back doesn't hide the ones after it; that's
+    /// for `verify()` to report.
+    fn intact_end(&self, start: usize) -> usize {
+        let word_len = mem::size_of::<Word>();
+        let body = &self.file_bytes()[JournalHeader::<H>::LEN ..];
+        let (_, end) = marks_in(as_words(body), start / word_len).fold((start, start), |(prev, end), mark| {
+            let mark_start = mark * word_len;
+            let mark_end = mark_start + word_len;
+            if !self.flags.digests || digest::check(&body[prev .. mark_start]) {
+                (mark_end, mark_end)
+            } else {
+                (mark_end, end)
+            }
+        });
+        end
+    }
+
+    /// Returns the roots committed after the current last mark.
+    ///
+    /// The iterator ends once it has caught up with what this journal can see. It doesn't refresh
+    /// the journal itself: to wait for more commits, call `refresh()`, eg after sleeping or on a
+    /// file change notification, then carry on iterating. Each item is the mark and the root it
+    /// commits.
+    pub fn follow<'v, T>(&'v self) -> Follow<'v, 'p, H, T>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        Follow {
+            marker: PhantomData,
+            journal: self,
+            next_word: self.marks().last().map(|mark| mark + 1).unwrap_or(0),
+        }
+    }
+
+    /// Returns the whole file, up to the current length, including any uncommitted bytes.
+    fn file_bytes(&self) -> &[u8] {
+        &self.mapping.as_bytes()[.. self.len()]
+    }
+
+    /// Returns the file up to the end of the last intact mark.
+    ///
+    /// Everything a reader sees goes through this, so bytes a writer hasn't committed yet, or a
+    /// torn commit that hasn't been discarded, are never exposed.
+    fn bytes(&self) -> &[u8] {
+        &self.mapping.as_bytes()[.. self.committed_len()]
+    }
+
+    /// Returns everything committed after the header.
+    fn body(&self) -> &[u8] {
+        &self.bytes()[JournalHeader::<H>::LEN ..]
     }
 
     #[must_use]
     fn words(&self) -> &[Le<u64>] {
-        let (_, bytes) = self.mapping_parts();
-        let (prefix, words, _) = unsafe { bytes.align_to::<Word>() };
-        assert_eq!(prefix.len(), 0);
-        words
+        as_words(self.body())
     }
 
     #[must_use]
     pub fn marks(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
-        self.words().into_iter()
-            .enumerate()
-            .filter_map(move |(idx, word)| {
-                if word.get() == !(idx as u64) {
-                    Some(idx)
-                } else {
-                    None
-                }
-            })
+        self.marks_from(0)
+    }
+
+    /// Returns the marks at or after word index `start`.
+    fn marks_from(&self, start: usize) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        marks_in(self.words(), start)
     }
 
     pub fn roots<'v>(&'v self) -> impl DoubleEndedIterator<Item = TryPile<'p, 'v>> {
         self.marks().map(move |idx| {
-            let slice = &self.mapping[mem::size_of::<JournalHeader<H>>() .. idx];
-            unsafe { TryPile::new_unchecked(slice) }
+            unsafe { TryPile::new_unchecked(self.slice_at(idx)) }
         })
     }
+
+    /// Returns the length of the journal up to and including the last intact mark.
+    ///
+    /// This includes the header, so an empty journal has a non-zero committed length.
+    pub fn committed_len(&self) -> usize {
+        self.committed_len.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of bytes after the last intact mark.
+    ///
+    /// These were written by a commit that never completed, eg due to a crash, or have been
+    /// zeroed since by `JournalMut::discard_uncommitted()`.
+    pub fn uncommitted_len(&self) -> usize {
+        self.len() - self.committed_len()
+    }
+
+    /// Returns the bytes committed by the mark at word index `mark`, excluding the mark itself.
+    fn slice_at(&self, mark: usize) -> &[u8] {
+        &self.body()[.. mark * mem::size_of::<Word>()]
+    }
+
+    /// Returns the offset of the `blob_len` byte root committed by the mark at word index `mark`.
+    fn root_offset(&self, mark: usize, blob_len: usize) -> Result<Offset<'p, 'static>, NotAMarkError> {
+        match self.words().get(mark) {
+            Some(word) if word.get() == !(mark as u64) => {},
+            _ => return Err(NotAMarkError(mark)),
+        }
+
+        let blob_len = WordOffset::align(blob_len).get() + self.trailer_len();
+        (mark * mem::size_of::<Word>()).checked_sub(blob_len)
+                                       .and_then(Offset::new_any)
+                                       .ok_or(NotAMarkError(mark))
+    }
+
+    /// Returns the root committed by the mark at word index `mark`.
+    ///
+    /// The root blob is the item written immediately before the mark. It, and everything reachable
+    /// from it, is validated before the `Bag` is returned, so a corrupt journal can't cause a
+    /// panic later on.
+    pub fn root_at<'v, T>(&'v self, mark: usize) -> Result<Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>, RootError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        let offset = self.root_offset(mark, T::BLOB_LEN)?;
+        let pile = unsafe { TryPile::new_unchecked(self.slice_at(mark)) };
+
+        pile.validate_deep::<T>(offset)
+            .map_err(|err| RootError::Invalid { mark, err })?;
+
+        // Safe as everything reachable from the root was just validated.
+        let pile = unsafe { Pile::new_unchecked(self.slice_at(mark)) };
+        let own = unsafe { Own::new_unchecked(Fat::new(offset.into(), T::make_sized_metadata())) };
+        Ok(Bag::from_parts(own, pile))
+    }
+
+    /// Checks the digest of every commit, oldest first.
+    ///
+    /// Each commit's digest covers the bytes between the previous mark and its own. A damaged
+    /// mark merges two commits, so the later of the two is then reported as corrupt. Unlike
+    /// `marks()`, marks after the last intact one are included, so a torn commit shows up too.
+    pub fn verify(&self) -> Vec<(usize, CommitStatus)> {
+        let body = &self.file_bytes()[JournalHeader::<H>::LEN ..];
+        let mut start = 0;
+        marks_in(as_words(body), 0).map(|mark| {
+            let end = mark * mem::size_of::<Word>();
+            let status = if !self.flags.digests {
+                CommitStatus::Unchecked
+            } else if digest::check(&body[start .. end]) {
+                CommitStatus::Intact
+            } else {
+                CommitStatus::Corrupt
+            };
+            start = end + mem::size_of::<Word>();
+            (mark, status)
+        }).collect()
+    }
+
+    /// Returns the root committed by the most recent mark, if any.
+    pub fn latest_root<'v, T>(&'v self) -> Result<Option<Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>>, RootError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.marks().last()
+            .map(|mark| self.root_at(mark))
+            .transpose()
+    }
+
+    /// Returns every root, newest first.
+    ///
+    /// Each item is the mark, the commit index counting from zero for the oldest commit, and the
+    /// root itself as it was when committed.
+    pub fn history<'v, T>(&'v self)
+        -> impl DoubleEndedIterator<Item = Result<(usize, usize, Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>), RootError>>
+                + ExactSizeIterator + 'v
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.marks().collect::<Vec<_>>()
+            .into_iter()
+            .enumerate()
+            .rev()
+            .map(move |(idx, mark)| {
+                self.root_at(mark).map(|root| (mark, idx, root))
+            })
+    }
+
+    /// Returns the mark of the commit with index `commit_index`, counting from zero for the
+    /// oldest commit.
+    pub fn commit_mark(&self, commit_index: usize) -> Option<usize> {
+        self.marks().nth(commit_index)
+    }
+
+    /// Returns the root committed by the commit with index `commit_index`, if there is one.
+    pub fn root_at_commit<'v, T>(&'v self, commit_index: usize)
+        -> Result<Option<Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>>, RootError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.commit_mark(commit_index)
+            .map(|mark| self.root_at(mark))
+            .transpose()
+    }
+
+    /// Returns the catalog committed by the mark at word index `mark`.
+    pub fn catalog_at<'v>(&'v self, mark: usize) -> Result<Catalog<'p, 'v>, CatalogError> {
+        if !self.flags.catalog {
+            return Err(CatalogError::NotACatalog { mark });
+        }
+        let root = self.root_at::<CatalogRoot>(mark)?;
+        Ok(Catalog::new(root, self.slice_at(mark), mark))
+    }
+
+    /// Returns the catalog committed by the most recent mark, if any.
+    pub fn latest_catalog<'v>(&'v self) -> Result<Option<Catalog<'p, 'v>>, CatalogError> {
+        self.marks().last()
+            .map(|mark| self.catalog_at(mark))
+            .transpose()
+    }
+
+    /// Returns the root called `name` in the most recent catalog.
+    ///
+    /// Returns `None` if nothing has been committed yet, or if the catalog has no such root.
+    pub fn root<'v, T>(&'v self, name: &str)
+        -> Result<Option<Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>>, CatalogError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        match self.latest_catalog()? {
+            Some(catalog) => catalog.get(name),
+            None => Ok(None),
+        }
+    }
 }
 
+/// Iterator returned by `Journal::follow()`.
+///
+/// Returns `None` once it has caught up, but isn't fused: after a `Journal::refresh()` that
+/// picks up new commits, it returns their roots.
 #[derive(Debug)]
-pub struct JournalMut<'p, H> {
-    fd: File,
+pub struct Follow<'v, 'p, H, T> {
+    marker: PhantomData<fn() -> T>,
+    journal: &'v Journal<'p, H>,
+    next_word: usize,
+}
+
+impl<'v, 'p, H: Primitive, T> Iterator for Follow<'v, 'p, H, T>
+where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+{
+    type Item = Result<(usize, Bag<T, OffsetMut<'p, 'v>, Pile<'p, 'v>>), RootError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mark = self.journal.marks_from(self.next_word).next()?;
+        self.next_word = mark + 1;
+        Some(self.journal.root_at(mark).map(|root| (mark, root)))
+    }
+}
+
+/// Views a journal body as words.
+fn as_words(body: &[u8]) -> &[Word] {
+    let (prefix, words, _) = unsafe { body.align_to::<Word>() };
+    assert_eq!(prefix.len(), 0);
+    words
+}
+
+/// Returns the marks in `words` at or after word index `start`.
+fn marks_in(words: &[Word], start: usize) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    words.get(start ..).unwrap_or(&[])
+        .iter()
+        .enumerate()
+        .filter_map(move |(idx, word)| {
+            let idx = start + idx;
+            if word.get() == !(idx as u64) {
+                Some(idx)
+            } else {
+                None
+            }
+        })
+}
+
+/// Returns true if both are the metadata of the same file.
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Without inode numbers to compare, assume they are, which is only ever overly cautious.
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
+
+#[derive(Debug)]
+pub struct JournalMut<'p, H, F = File> {
+    fd: F,
     journal: Journal<'p, H>,
+    durability: Durability,
+    unsynced_commits: usize,
+    last_sync: Instant,
+    flush_threshold: usize,
+    dedup: Option<Dedup<Offset<'static, 'static>>>,
+
+    /// Set once the file has been replaced by compaction, after which commits would be lost.
+    compacted: AtomicBool,
 }
 
-impl<'p, H> JournalMut<'p, H> {
-    pub fn create(path: impl AsRef<Path>, header: H) -> io::Result<Self> {
+impl<'p, H: Primitive> JournalMut<'p, H> {
+    pub fn create(path: impl AsRef<Path>, header: H) -> Result<Self, JournalError> {
+        Self::create_with_flags(path, header, JournalFlags::default())
+    }
+
+    pub fn create_with_flags(path: impl AsRef<Path>, header: H, flags: JournalFlags) -> Result<Self, JournalError> {
         let fd = OpenOptions::new()
                              .read(true)
-                             .append(true)
+                             .write(true)
                              .create_new(true)
                              .open(path)?;
-        Self::create_from_fd(fd, header)
+        Lock::FailFast.lock_exclusive(&fd)?;
+        Self::create_from_fd_with_flags(fd, header, flags)
     }
 
-    pub fn create_from_fd(mut fd: File, header: H) -> io::Result<Self> {
-        let header = JournalHeader::new(header);
-        fd.write(header.as_bytes())?;
-
-        Self::open_fd(fd)
+    /// Opens a journal for writing, taking an exclusive lock on it.
+    ///
+    /// Fails with `JournalError::Locked` if another `JournalMut` or `Journal` has the file open,
+    /// unless that `Journal` was opened with `Lock::None`. The lock is held until this and every
+    /// snapshot taken from it have been dropped.
+    ///
+    /// If `append` is false the file is opened read-only, and any uncommitted bytes are left as
+    /// they are rather than discarded.
+    pub fn open(path: impl AsRef<Path>, append: bool) -> Result<Self, JournalError> {
+        Self::open_with_lock(path, append, Lock::default())
     }
 
-    pub fn open(path: impl AsRef<Path>, append: bool) -> io::Result<Self> {
+    pub fn open_with_lock(path: impl AsRef<Path>, append: bool, lock: Lock) -> Result<Self, JournalError> {
         let fd = OpenOptions::new()
                              .read(true)
-                             .append(append)
+                             .write(append)
                              .open(path)?;
+        lock.lock_exclusive(&fd)?;
+        Self::from_fd(fd, append)
+    }
+
+    /// Compacts the journal down to its latest root.
+    ///
+    /// See `compact_roots()`.
+    pub fn compact<'v, T>(&'v self, path: impl AsRef<Path>) -> Result<Self, JournalError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        let latest = self.journal.marks().last();
+        self.compact_roots::<T>(path, latest)
+    }
+
+    /// Writes a fresh journal containing only the roots committed by `marks`, then atomically
+    /// replaces the file at `path` with it.
+    ///
+    /// Everything reachable from each root is loaded and saved again, so the new journal has new
+    /// offsets and none of the unreachable bytes. Each root gets a commit of its own. Blobs are
+    /// deduplicated while compacting, so structure shared between roots is only written once.
+    ///
+    /// `path` is normally this journal's own path. The copy is written next to it and renamed
+    /// into place once synced, so a crash part way through leaves the original intact. The
+    /// compacted journal is returned. Once the file has been replaced, commits to this journal
+    /// fail, as they'd go to a file that's no longer linked. If `path` is some other file, this
+    /// journal isn't replaced, and can still be committed to.
+    ///
+    /// Journals with the catalog flag are compacted with `compact_catalogs()` instead.
+    pub fn compact_roots<'v, T>(&'v self, path: impl AsRef<Path>, marks: impl IntoIterator<Item = usize>)
+        -> Result<Self, JournalError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        if self.journal.flags.catalog {
+            return Err(JournalError::CatalogFlag { expected: false });
+        }
+
+        self.compact_with(path, |dst| {
+            for mark in marks {
+                let (own, pile) = self.journal.root_at::<T>(mark)
+                                      .map_err(|err| JournalError::Root { mark, err })?
+                                      .into_parts();
+                dst.write_compacted_root(&*own.get_in(&pile), pile)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Like `compact_roots()`, for journals with the catalog flag.
+    ///
+    /// A catalog doesn't record the types of its roots, so `copy` is called with each catalog
+    /// in turn, and copies its roots into the writer it's given, normally with
+    /// `CatalogWriter::copy_root()`. Roots it doesn't copy are dropped.
+    pub fn compact_catalogs<'v, C>(&'v self, path: impl AsRef<Path>, marks: impl IntoIterator<Item = usize>,
+                                   mut copy: C)
+        -> Result<Self, JournalError>
+        where C: for<'a> FnMut(&Catalog<'p, 'v>, CatalogWriter<'a, 'p, H>) -> io::Result<CatalogWriter<'a, 'p, H>>
+    {
+        if !self.journal.flags.catalog {
+            return Err(JournalError::CatalogFlag { expected: true });
+        }
+
+        self.compact_with(path, |dst| {
+            for mark in marks {
+                let catalog = self.journal.catalog_at(mark)
+                                  .map_err(|err| JournalError::Catalog { mark, err })?;
+                copy(&catalog, dst.catalog_writer()?)?.commit()?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes a fresh journal with `f`, then renames it over `path`.
+    fn compact_with(&self, path: impl AsRef<Path>, f: impl FnOnce(&mut Self) -> Result<(), JournalError>)
+        -> Result<Self, JournalError>
+    {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        // Left behind by a compaction that never finished.
+        match fs::remove_file(&tmp_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {},
+        }
+
+        let mut fd = OpenOptions::new()
+                                 .read(true)
+                                 .write(true)
+                                 .create_new(true)
+                                 .open(&tmp_path)?;
+        Lock::FailFast.lock_exclusive(&fd)?;
+        fd.write_all(&self.journal.bytes()[.. JournalHeader::<H>::LEN])?;
+
+        let mut dst = Self::open_fd(fd)?;
+        dst.set_durability(Durability::None);
+        dst.set_dedup(true);
+
+        f(&mut dst)?;
+        dst.sync()?;
+
+        let in_place = match fs::metadata(path) {
+            Ok(metadata) => same_file(&metadata, &self.fd.metadata()?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => return Err(err.into()),
+        };
+
+        fs::rename(&tmp_path, path)?;
+        if in_place {
+            self.compacted.store(true, Ordering::Release);
+        }
+
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+
+        dst.set_durability(self.durability);
+        dst.set_dedup(self.dedup());
+        Ok(dst)
+    }
+}
+
+impl<'p, H: Primitive, F: JournalFile> JournalMut<'p, H, F> {
+    /// The default for `set_flush_threshold()`.
+    pub const DEFAULT_FLUSH_THRESHOLD: usize = 1024 * 1024;
+
+    pub fn create_from_fd(fd: F, header: H) -> Result<Self, JournalError> {
+        Self::create_from_fd_with_flags(fd, header, JournalFlags::default())
+    }
+
+    pub fn create_from_fd_with_flags(mut fd: F, header: H, flags: JournalFlags) -> Result<Self, JournalError> {
+        fd.write_all(&JournalHeader::encode(flags, &header))?;
+
         Self::open_fd(fd)
     }
 
-    pub fn open_fd(fd: File) -> io::Result<Self> {
-        let mapping = unsafe { Mmap::map(&fd)? };
+    /// Opens a journal for writing.
+    ///
+    /// Any bytes after the last intact mark are discarded, so the journal reopens at the last
+    /// committed root.
+    pub fn open_fd(fd: F) -> Result<Self, JournalError> {
+        Self::from_fd(fd, true)
+    }
 
-        Ok(Self {
-            journal: Journal::open_fd(&fd)?,
+    fn from_fd(fd: F, discard_uncommitted: bool) -> Result<Self, JournalError> {
+        let mut this = Self {
+            journal: Journal::open_fd(fd.file())?,
             fd,
-        })
+            durability: Durability::default(),
+            unsynced_commits: 0,
+            last_sync: Instant::now(),
+            flush_threshold: Self::DEFAULT_FLUSH_THRESHOLD,
+            dedup: None,
+            compacted: AtomicBool::new(false),
+        };
+        if discard_uncommitted {
+            this.discard_uncommitted()?;
+        }
+        Ok(this)
+    }
+
+    fn reload_mapping(&mut self) -> Result<(), JournalError> {
+        self.journal.refresh()?;
+        Ok(())
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Sets when commits are synced; takes effect from the next commit.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    pub fn flush_threshold(&self) -> usize {
+        self.flush_threshold
+    }
+
+    /// Sets how many bytes of items are buffered before they're written to the file.
+    ///
+    /// Buffered items are written once the threshold is reached, rather than all at once when
+    /// committing, so a large save needs only about this much memory beyond the tree itself.
+    pub fn set_flush_threshold(&mut self, bytes: usize) {
+        self.flush_threshold = bytes;
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.dedup.is_some()
     }
 
-    fn reload_mapping(&mut self) -> io::Result<()> {
-        self.journal.mapping = Journal::<H>::make_mapping(&self.fd)?;
+    /// Sets whether identical blobs are only written once.
+    ///
+    /// When enabled, a blob with the same type and bytes as one already written by this
+    /// `JournalMut`, in this commit or an earlier one, is saved as a pointer to the existing copy.
+    /// Blobs written before dedup was enabled, or by an earlier `JournalMut`, aren't known about.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        if dedup != self.dedup() {
+            self.dedup = if dedup { Some(Dedup::new()) } else { None };
+        }
+    }
+
+    /// Returns the number of commits since the last sync.
+    pub fn unsynced_commits(&self) -> usize {
+        self.unsynced_commits
+    }
+
+    /// Syncs any commits that haven't been synced yet.
+    ///
+    /// With `Durability::Group` the last few commits are only synced by a later commit, as
+    /// there's no background timer. Callers must call this when they go idle, and before relying
+    /// on a commit having reached stable storage.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.unsynced_commits > 0 {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs all commits so far to stable storage.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.fd.sync_data()?;
+        self.unsynced_commits = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Syncs after a mark is written, if the durability setting calls for it.
+    fn sync_committed(&mut self) -> io::Result<()> {
+        self.unsynced_commits += 1;
+        let due = match self.durability {
+            Durability::None => false,
+            Durability::Always => true,
+            Durability::Group { commits, interval } => {
+                self.unsynced_commits >= commits || self.last_sync.elapsed() >= interval
+            },
+        };
+
+        if due {
+            self.sync()?;
+        }
         Ok(())
     }
 
+    /// Discards any bytes after the last intact mark, returning how many there were.
+    ///
+    /// They're overwritten with zeros, which are never mistaken for a mark, rather than truncated:
+    /// snapshots may have them mapped, and touching a mapped page past the end of a file is a
+    /// fatal error. The next commit is written over them.
+    pub fn discard_uncommitted(&mut self) -> io::Result<usize> {
+        let committed_len = self.journal.committed_len() as u64;
+        let file_len = self.fd.seek(SeekFrom::End(0))?;
+
+        let uncommitted_len = file_len.saturating_sub(committed_len);
+        if uncommitted_len > 0 {
+            self.fd.seek(SeekFrom::Start(committed_len))?;
+            io::copy(&mut io::repeat(0).take(uncommitted_len), &mut self.fd)?;
+        }
+        self.fd.seek(SeekFrom::Start(committed_len))?;
+        Ok(uncommitted_len as usize)
+    }
+
     pub fn snapshot(&self) -> Journal<'p, H> {
         self.journal.clone()
     }
 
-    pub fn write_root<'v, 'a: 'v, T>(&'a mut self, root: &T) -> io::Result<()>
+    /// Starts a commit of several named roots under one mark.
+    ///
+    /// Fails unless the journal has the catalog flag.
+    pub fn catalog_writer(&mut self) -> io::Result<CatalogWriter<'_, 'p, H, F>> {
+        if !self.journal.flags.catalog {
+            return Err(JournalError::CatalogFlag { expected: true }.into());
+        }
+        Ok(CatalogWriter::new(JournalWriter::new(self)?))
+    }
+
+    /// Saves a root, and everything dirty reachable from it, then commits.
+    ///
+    /// Clean offsets are reused as-is. Returns the offset of the root blob.
+    pub fn write_root<'v, 'a: 'v, T>(&'a mut self, root: &T) -> io::Result<Offset<'static, 'static>>
         where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>,
     {
+        self.save_root(root)?.commit()
+    }
+
+    /// Starts saving a root, without committing it.
+    ///
+    /// The save can be done a chunk at a time with `RootSave::poll()`, letting the caller do
+    /// other work in between.
+    ///
+    /// Fails if the journal has the catalog flag, as every commit to it must be a catalog.
+    pub fn save_root<'v, 'a: 'v, T>(&'a mut self, root: &T) -> io::Result<RootSave<'v, 'p, H, F, T::SavePoll>>
+        where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>,
+    {
+        if self.journal.flags.catalog {
+            return Err(JournalError::CatalogFlag { expected: false }.into());
+        }
         let writer = JournalWriter::new(self)?;
+        let poll = root.init_save(&writer);
+        Ok(RootSave { writer, poll })
+    }
 
-        let mut poll = root.init_save(&writer);
-        let writer = poll.save_poll(writer)?;
+    /// Saves and commits a root, provided the tip mark is still `expected_mark`.
+    ///
+    /// `expected_mark` is the last mark returned by `Journal::marks()` when the root was read, or
+    /// `None` if the journal was empty. The journal is reloaded first, so commits made through
+    /// other handles to the same file are seen. If the tip has moved, nothing is written and
+    /// `JournalError::Conflict` is returned; the caller should reload, rebase, and try again.
+    pub fn commit_if<'v, 'a: 'v, T>(&'a mut self, expected_mark: Option<usize>, root: &T)
+        -> Result<Offset<'static, 'static>, JournalError>
+        where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>,
+    {
+        self.reload_mapping()?;
 
-        Ok(())
+        let actual = self.journal.marks().last();
+        if actual != expected_mark {
+            return Err(JournalError::Conflict { expected: expected_mark, actual });
+        }
+        Ok(self.write_root(root)?)
+    }
+
+    /// Like `write_root()`, but clean pointers are loaded from `pile` and written again.
+    fn write_compacted_root<'v, T>(&mut self, root: &T, pile: Pile<'p, 'v>) -> io::Result<Offset<'static, 'static>>
+        where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>,
+    {
+        let compactor = Compactor {
+            pile,
+            writer: JournalWriter::new(self)?,
+        };
+
+        let mut poll = root.init_save(&compactor);
+        let mut compactor = poll.save_poll(compactor)?;
+        let offset = compactor.writer.write_blob(&poll)?;
+
+        compactor.writer.commit()?;
+        Ok(offset)
     }
 }
 
+/// A root being saved, a chunk at a time.
+///
+/// Created by `JournalMut::save_root()`. Nothing is committed until `commit()` is called.
 #[derive(Debug)]
-pub struct JournalWriter<'a, 'p: 'a, H> {
-    journal: &'a mut JournalMut<'p, H>,
+pub struct RootSave<'a, 'p, H, F, S> {
+    writer: JournalWriter<'a, 'p, H, F>,
+    poll: S,
+}
+
+impl<'a, 'p, H: Primitive, F: JournalFile, S> RootSave<'a, 'p, H, F, S>
+where S: SavePoll<OffsetMut<'p, 'a>, Offset<'static, 'static>> + SaveBlob
+{
+    /// Saves until at least `chunk` more bytes have been written, or everything reachable from
+    /// the root has been.
+    ///
+    /// Returns `true` once everything has been saved; the root itself is written by `commit()`.
+    pub fn poll(&mut self, chunk: usize) -> io::Result<bool> {
+        let dst = Chunk {
+            writer: &mut self.writer,
+            remaining: chunk,
+        };
+        match self.poll.save_poll(dst) {
+            Ok(_) => Ok(true),
+            Err(ChunkError::Full) => Ok(false),
+            Err(ChunkError::Io(err)) => Err(err),
+        }
+    }
+
+    /// Saves whatever is left, then writes the root and commits.
+    ///
+    /// Returns the offset of the root blob.
+    pub fn commit(self) -> io::Result<Offset<'static, 'static>> {
+        let Self { writer, mut poll } = self;
+
+        let mut writer = poll.save_poll(writer)?;
+        let offset = writer.write_blob(&poll)?;
+
+        writer.commit()?;
+        Ok(offset)
+    }
+}
+
+/// Saves through a `JournalWriter` until a number of bytes have been written.
+#[derive(Debug)]
+struct Chunk<'w, 'a, 'p, H, F> {
+    writer: &'w mut JournalWriter<'a, 'p, H, F>,
+    remaining: usize,
+}
+
+#[derive(Debug)]
+enum ChunkError {
+    Full,
+    Io(io::Error),
+}
+
+impl<'w, 'a, 'p, H: Primitive, F: JournalFile> SavePtr for Chunk<'w, 'a, 'p, H, F> {
+    type Source = OffsetMut<'p, 'a>;
+    type Target = Offset<'static, 'static>;
+    type Error = ChunkError;
+
+    unsafe fn check_dirty<'b, T: ?Sized>(&self, ptr: &'b Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'b, T>>
+        where T: Load<Self::Source> + Save<Self::Source, Self::Target>
+    {
+        self.writer.check_dirty::<T>(ptr, metadata)
+    }
+
+    fn try_save_ptr(mut self, saver: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        // The blob isn't written, so the encoder that's saving it will try again next time.
+        if self.remaining == 0 {
+            return Err(ChunkError::Full);
+        }
+
+        let start = self.writer.offset;
+        let offset = self.writer.save_blob(saver).map_err(ChunkError::Io)?;
+        let written = (self.writer.offset - start).get();
+        self.remaining = self.remaining.saturating_sub(written);
+        Ok((self, offset))
+    }
+}
+
+/// Converts the offset of an item to a pile offset, failing if the journal has grown too large.
+fn item_offset(offset: WordOffset) -> io::Result<Offset<'static, 'static>> {
+    Offset::new(offset.get())
+           .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "journal offset overflow"))
+}
+
+#[derive(Debug)]
+pub struct JournalWriter<'a, 'p: 'a, H, F = File> {
+    journal: &'a mut JournalMut<'p, H, F>,
     buffer: Vec<u8>,
     offset: WordOffset,
+    hasher: Option<Sha256>,
+    pending: Dedup<Offset<'static, 'static>>,
 }
 
-impl<'a, 'p, H> JournalWriter<'a, 'p, H> {
-    pub fn new(journal: &'a mut JournalMut<'p, H>) -> io::Result<Self> {
-        let pos = journal.fd.seek(SeekFrom::End(0))?;
+impl<'a, 'p, H: Primitive, F: JournalFile> JournalWriter<'a, 'p, H, F> {
+    pub fn new(journal: &'a mut JournalMut<'p, H, F>) -> io::Result<Self> {
+        if journal.compacted.load(Ordering::Acquire) {
+            return Err(io::Error::new(io::ErrorKind::Other, "journal has been replaced by compaction"));
+        }
+
+        // Left behind by a previous write that failed part way through.
+        journal.discard_uncommitted()?;
 
-        let pos: usize = pos.checked_sub(mem::size_of::<JournalHeader<H>>() as u64)
-                            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "journal truncated"))?
-                            .try_into().expect("FIXME");
+        let pos = journal.journal.committed_len() - JournalHeader::<H>::LEN;
         let offset = WordOffset::align(pos);
 
         // FIXME: make sure the padding doesn't create a mark
@@ -179,10 +986,17 @@ impl<'a, 'p, H> JournalWriter<'a, 'p, H> {
 
         journal.fd.write_all(padding)?;
 
+        let mut hasher = if journal.journal.flags.digests { Some(digest::hasher()) } else { None };
+        if let Some(hasher) = &mut hasher {
+            hasher.input(padding);
+        }
+
         Ok(Self {
             journal,
             offset,
             buffer: vec![],
+            hasher,
+            pending: Dedup::new(),
         })
     }
 
@@ -191,8 +1005,42 @@ impl<'a, 'p, H> JournalWriter<'a, 'p, H> {
         ItemWriter::new(&mut self.buffer, &mut self.offset, len)
     }
 
+    /// Writes a blob as an item, flushing if the buffer has reached the journal's threshold.
+    fn save_blob(&mut self, saver: &impl SaveBlob) -> io::Result<Offset<'static, 'static>> {
+        // New blobs are only remembered on commit, as an abandoned save's blobs are discarded.
+        let offset = if let Some(dedup) = &self.journal.dedup {
+            let (buffer, offset) = (&mut self.buffer, &mut self.offset);
+            dedup.save_blob_pending(&mut self.pending, saver, |bytes| {
+                let mut item = ItemWriter::new(buffer, offset, bytes.len());
+                item.write_bytes(bytes);
+                item_offset(item.finish())
+            })?
+        } else {
+            self.write_blob(saver)?
+        };
+
+        // Items are only ever flushed whole. Padding to avoid marks depends only on an item's
+        // offset, not on what else is in the buffer, so flushing doesn't change what's written.
+        if self.buffer.len() >= self.journal.flush_threshold {
+            self.flush()?;
+        }
+
+        Ok(offset)
+    }
+
+    /// Writes a blob as an item, even if an identical blob has already been saved.
+    ///
+    /// Used for roots, which have to be the last item before the mark.
+    fn write_blob(&mut self, saver: &impl SaveBlob) -> io::Result<Offset<'static, 'static>> {
+        let offset = saver.save_blob(ItemAllocator(self))?;
+        item_offset(offset)
+    }
+
     pub fn flush(&mut self) -> io::Result<()> {
         self.journal.fd.write_all(&self.buffer)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.input(&self.buffer);
+        }
         self.buffer.clear();
         Ok(())
     }
@@ -200,37 +1048,89 @@ impl<'a, 'p, H> JournalWriter<'a, 'p, H> {
     pub fn commit(&mut self) -> io::Result<WordOffset> {
         self.flush()?;
 
+        if let Some(hasher) = self.hasher.replace(digest::hasher()) {
+            self.journal.fd.write_all(&digest::encode_trailer(hasher))?;
+            self.offset += WordOffset::align(digest::TRAILER_LEN);
+        }
+
+        // Whatever the durability, a mark must never reach the disk before the data it commits.
+        if self.journal.durability != Durability::None {
+            self.journal.fd.sync_data()?;
+        }
+
         let idx_words = self.offset.get() / mem::size_of::<Word>();
-        let mark = (!idx_words).to_le_bytes();
-        self.journal.fd.write(&mark)?;
+        let mark = (!(idx_words as u64)).to_le_bytes();
+        self.journal.fd.write_all(&mark)?;
         self.offset += WordOffset::WORD;
+        self.journal.sync_committed()?;
         self.journal.reload_mapping()?;
 
-        // FIXME: verify mapping is correct size/file hasn't been truncated
+        // The mark only counts if it's intact, eg if nothing else wrote to the file meanwhile.
+        let expected = (JournalHeader::<H>::LEN + self.offset.get()) as u64;
+        let actual = self.journal.journal.committed_len() as u64;
+        if actual != expected {
+            return Err(JournalError::Truncated { expected, actual }.into());
+        }
+
+        if let Some(dedup) = &mut self.journal.dedup {
+            dedup.extend(mem::take(&mut self.pending));
+        }
+
         Ok(self.offset - WordOffset::WORD)
     }
 }
 
-impl<'p, 'v, H> SavePtr for JournalWriter<'v, 'p, H> {
+impl<'p, 'v, H: Primitive, F: JournalFile> SavePtr for JournalWriter<'v, 'p, H, F> {
+    type Source = OffsetMut<'p, 'v>;
+    type Target = Offset<'static, 'static>;
+    type Error = io::Error;
+
+    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'a, T>>
+        where T: Load<Self::Source>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Err(Ref::Ref(r)),
+            Err(offset) => Ok(offset.to_static()),
+        }
+    }
+
+    fn try_save_ptr(mut self, saver: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        let offset = self.save_blob(saver)?;
+        Ok((self, offset))
+    }
+}
+
+/// Saves into a journal, treating every pointer as dirty.
+///
+/// Clean pointers are loaded from the pile they were committed in, so that everything reachable
+/// is copied rather than left pointing into the old journal.
+#[derive(Debug)]
+struct Compactor<'a, 'p, 'v, H, F> {
+    pile: Pile<'p, 'v>,
+    writer: JournalWriter<'a, 'p, H, F>,
+}
+
+impl<'a, 'p, 'v, H: Primitive, F: JournalFile> SavePtr for Compactor<'a, 'p, 'v, H, F> {
     type Source = OffsetMut<'p, 'v>;
     type Target = Offset<'static, 'static>;
     type Error = io::Error;
 
-    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, &'a T>
-        where T: Pointee
+    unsafe fn check_dirty<'b, T: ?Sized>(&self, ptr: &'b Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'b, T>>
+        where T: Load<Self::Source>
     {
-        todo!()
+        Err(self.pile.get_unchecked::<T>(ptr, metadata))
     }
 
     fn try_save_ptr(self, saver: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
-        todo!()
+        let (writer, offset) = self.writer.try_save_ptr(saver)?;
+        Ok((Self { pile: self.pile, writer }, offset))
     }
 }
 
 #[derive(Debug)]
-pub struct ItemAllocator<'a, 'v, 'p, H>(&'a mut JournalWriter<'v, 'p, H>);
+pub struct ItemAllocator<'a, 'v, 'p, H, F = File>(&'a mut JournalWriter<'v, 'p, H, F>);
 
-impl<'a, 'p, 'v, H> save::AllocBlob for ItemAllocator<'a, 'v, 'p, H> {
+impl<'a, 'p, 'v, H: Primitive, F: JournalFile> save::AllocBlob for ItemAllocator<'a, 'v, 'p, H, F> {
     type WriteBlob = ItemWriter<'a>;
     type Error = io::Error;
     type Done = WordOffset;
@@ -316,7 +1216,10 @@ impl<'a> ItemWriter<'a> {
         let padding_len_bytes = padding_len_words * mem::size_of::<Word>();
         if padding_len_bytes > 0 {
             self.buffer.resize(self.buffer.len() + padding_len_bytes, 0);
-            self.buffer.copy_within(self.offset.get() .. written_bytes_len, self.offset.get() + padding_len_bytes);
+            self.buffer.copy_within(start .. start + written_bytes_len, start + padding_len_bytes);
+
+            // The padding itself must not contain any of the item's bytes, or they'd be marks.
+            self.buffer[start .. start + padding_len_bytes].iter_mut().for_each(|b| *b = 0);
 
             *self.offset += WordOffset::try_from(padding_len_bytes).unwrap();
         }
@@ -360,24 +1263,51 @@ fn calc_conflicts_impl(offset: usize, words: &[Le<u64>], conflicts: &mut [bool])
     conflicts.len()
 }
 
-#[derive(Default)]
-struct JournalHeader<H = ()> {
-    magic: [u8; 16],
-    header: H,
-}
+/// The on-disk journal header.
+///
+/// A fixed magic and format version word, followed by the user header encoded as a blob, padded
+/// to a word boundary so that the rest of the journal is word aligned.
+struct JournalHeader<H = ()>(PhantomData<H>);
+
+impl<H: Primitive> JournalHeader<H> {
+    const MAGIC: [u8; 16] = *b"hoard journal\0\0\0";
+    const VERSION: u64 = 1;
+
+    const LEN: usize = Self::MAGIC.len() + 2 * mem::size_of::<Word>()
+                       + WordOffset::align(<H as ValidateBlob>::BLOB_LEN).get();
+
+    fn encode(flags: JournalFlags, header: &H) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&Self::MAGIC);
+        buf.extend_from_slice(&Self::VERSION.to_le_bytes());
+        buf.extend_from_slice(&flags.to_bits().to_le_bytes());
+        buf.extend_from_slice(&header.encode_blob_bytes());
+        buf.resize(Self::LEN, 0);
+        buf
+    }
 
-impl<H> JournalHeader<H> {
-    pub fn new(header: H) -> Self {
-        Self {
-            magic: [0; 16],
-            header,
+    fn decode(buf: &[u8]) -> Result<(JournalFlags, H), JournalError> {
+        assert_eq!(buf.len(), Self::LEN);
+        let (magic, rest) = buf.split_at(Self::MAGIC.len());
+        let (version, rest) = rest.split_at(mem::size_of::<Word>());
+        let (flags, rest) = rest.split_at(mem::size_of::<Word>());
+
+        if magic != Self::MAGIC {
+            return Err(JournalError::BadMagic);
         }
-    }
-    pub fn as_bytes(&self) -> &[u8] {
-        assert_eq!(mem::size_of::<H>(), 0);
-        unsafe {
-            slice::from_raw_parts(self as *const Self as *const u8, mem::size_of::<Self>())
+
+        let version = u64::from_le_bytes(version.try_into().unwrap());
+        if version != Self::VERSION {
+            return Err(JournalError::UnsupportedVersion { version });
         }
+
+        let flags = u64::from_le_bytes(flags.try_into().unwrap());
+        let flags = JournalFlags::from_bits(flags)
+                                 .ok_or(JournalError::UnsupportedFlags { flags })?;
+
+        let header = H::try_decode_blob_bytes(&rest[.. <H as ValidateBlob>::BLOB_LEN])
+                       .map_err(|err| JournalError::Header(err.to_string()))?;
+        Ok((flags, header))
     }
 }
 
@@ -429,7 +1359,6 @@ mod tests {
         let mut writer = ItemWriter::new(&mut dst, &mut offset, 16);
         writer.write_bytes(&[0xf0,0xff,0xff,0xff,0xff,0xff,0xff,0xff,
                              0xf0,0xff,0xff,0xff,0xff,0xff,0xff,0xff]);
-        dbg!(&writer);
         assert_eq!(writer.finish(), 0);
 
         // Conflicting on first word
@@ -447,6 +1376,397 @@ mod tests {
         writer.write_bytes(&[0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,
                              0xfd,0xff,0xff,0xff,0xff,0xff,0xff,0xff]);
         assert_eq!(writer.finish(), 16);
+
+        // Conflicting after an earlier item in the same buffer
+        let mut dst = vec![];
+        let mut offset = WordOffset::try_from(0).unwrap();
+        let mut writer = ItemWriter::new(&mut dst, &mut offset, 8);
+        writer.write_bytes(&[0xf0,0xff,0xff,0xff,0xff,0xff,0xff,0xff]);
+        assert_eq!(writer.finish(), 0);
+
+        let mut writer = ItemWriter::new(&mut dst, &mut offset, 8);
+        writer.write_bytes(&[0xfe,0xff,0xff,0xff,0xff,0xff,0xff,0xff]);
+        assert_eq!(writer.finish(), 16);
+        assert_eq!(dst, &[0xf0,0xff,0xff,0xff,0xff,0xff,0xff,0xff,
+                          0,0,0,0,0,0,0,0,
+                          0xfe,0xff,0xff,0xff,0xff,0xff,0xff,0xff]);
+    }
+
+    #[test]
+    fn journal_too_short() -> io::Result<()> {
+        let mut fd = tempfile()?;
+        match Journal::<()>::open_fd(&fd) {
+            Err(JournalError::TooShort { len: 0, header_len: 32 }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        fd.write_all(&[0; 15])?;
+        match JournalMut::<()>::open_fd(fd) {
+            Err(JournalError::TooShort { len: 15, header_len: 32 }) => {},
+            r => panic!("{:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn journal_header() -> Result<(), JournalError> {
+        let fd = tempfile()?;
+        let journal = JournalMut::create_from_fd(fd.try_clone()?, Le::new(0x1234_5678_u32))?;
+        assert_eq!(journal.snapshot().header().get(), 0x1234_5678);
+        drop(journal);
+
+        let snapshot = Journal::<Le<u32>>::open_fd(&fd)?;
+        assert_eq!(snapshot.header().get(), 0x1234_5678);
+        assert_eq!(JournalHeader::<Le<u32>>::LEN, 40);
+        assert_eq!(snapshot.len(), 40);
+
+        // Not a valid bool
+        match Journal::<bool>::open_fd(&fd) {
+            Err(JournalError::Header(_)) => {},
+            r => panic!("{:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn journal_header_rejected() -> io::Result<()> {
+        let mut fd = tempfile()?;
+        fd.write_all(&[0; 32])?;
+        match Journal::<()>::open_fd(&fd) {
+            Err(JournalError::BadMagic) => {},
+            r => panic!("{:?}", r),
+        }
+
+        let mut fd = tempfile()?;
+        fd.write_all(b"hoard journal\0\0\0")?;
+        fd.write_all(&2u64.to_le_bytes())?;
+        fd.write_all(&0u64.to_le_bytes())?;
+        match JournalMut::<()>::open_fd(fd) {
+            Err(JournalError::UnsupportedVersion { version: 2 }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        let mut fd = tempfile()?;
+        fd.write_all(b"hoard journal\0\0\0")?;
+        fd.write_all(&1u64.to_le_bytes())?;
+        fd.write_all(&4u64.to_le_bytes())?;
+        match Journal::<()>::open_fd(&fd) {
+            Err(JournalError::UnsupportedFlags { flags: 4 }) => {},
+            r => panic!("{:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn journal_verify() -> Result<(), JournalError> {
+        use std::os::unix::fs::FileExt;
+
+        let fd = tempfile()?;
+        let flags = JournalFlags { digests: true, ..Default::default() };
+        let mut journal = JournalMut::create_from_fd_with_flags(fd.try_clone()?, (), flags)?;
+        journal.write_root(&42u8)?;
+        journal.write_root(&OffsetMut::alloc(43u8))?;
+
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.flags(), flags);
+        let marks = snapshot.marks().collect::<Vec<_>>();
+        assert_eq!(snapshot.verify(), &[(marks[0], CommitStatus::Intact),
+                                        (marks[1], CommitStatus::Intact)]);
+
+        assert_eq!(*snapshot.root_at::<u8>(marks[0]).unwrap().get(), 42);
+        let (own, pile) = snapshot.latest_root::<Own<u8, OffsetMut>>().unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile), 43);
+
+        // Flip a bit in the first commit's root
+        fd.write_at(&[43], JournalHeader::<()>::LEN as u64)?;
+        let snapshot = Journal::<()>::open_fd(&fd)?;
+        assert_eq!(snapshot.verify(), &[(marks[0], CommitStatus::Corrupt),
+                                        (marks[1], CommitStatus::Intact)]);
+
+        // Damage further back doesn't hide later commits.
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), marks);
+        assert_eq!(snapshot.uncommitted_len(), 0);
+
+        // Without digests nothing is checked
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        journal.write_root(&42u8)?;
+        assert_eq!(journal.snapshot().verify(), &[(1, CommitStatus::Unchecked)]);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_durability() -> Result<(), JournalError> {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::time::Duration;
+
+        #[derive(Debug, PartialEq, Eq)]
+        enum Call {
+            Write(usize),
+            SyncData,
+        }
+
+        #[derive(Debug)]
+        struct Recorder {
+            file: File,
+            calls: Rc<RefCell<Vec<Call>>>,
+        }
+
+        impl Write for Recorder {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = self.file.write(buf)?;
+                self.calls.borrow_mut().push(Call::Write(n));
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.file.flush()
+            }
+        }
+
+        impl Seek for Recorder {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.file.seek(pos)
+            }
+        }
+
+        impl JournalFile for Recorder {
+            fn file(&self) -> &File {
+                &self.file
+            }
+
+            fn sync_data(&self) -> io::Result<()> {
+                self.calls.borrow_mut().push(Call::SyncData);
+                self.file.sync_data()
+            }
+        }
+
+        let calls = Rc::new(RefCell::new(vec![]));
+        let recorder = Recorder { file: tempfile()?, calls: calls.clone() };
+        let mut journal = JournalMut::create_from_fd(recorder, ())?;
+        assert_eq!(journal.durability(), Durability::Always);
+        calls.borrow_mut().clear();
+
+        // Data is synced before the mark, and the mark before commit returns.
+        journal.write_root(&42u8)?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::Write(8), Call::SyncData, Call::Write(8), Call::SyncData]);
+
+        journal.set_durability(Durability::None);
+        journal.write_root(&42u8)?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::Write(8), Call::Write(8)]);
+
+        // Data is still synced before every mark; only syncing the mark is batched. The unsynced
+        // commit above counts towards the group.
+        journal.set_durability(Durability::Group { commits: 3, interval: Duration::from_secs(3600) });
+        journal.write_root(&42u8)?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::Write(8), Call::SyncData, Call::Write(8)]);
+        assert_eq!(journal.unsynced_commits(), 2);
+        journal.write_root(&42u8)?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::Write(8), Call::SyncData, Call::Write(8), Call::SyncData]);
+        assert_eq!(journal.unsynced_commits(), 0);
+
+        journal.write_root(&42u8)?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::Write(8), Call::SyncData, Call::Write(8)]);
+        journal.flush()?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::SyncData]);
+
+        // Nothing left to sync
+        journal.flush()?;
+        assert_eq!(calls.replace(vec![]), &[]);
+
+        journal.set_durability(Durability::Group { commits: 3, interval: Duration::from_secs(0) });
+        journal.write_root(&42u8)?;
+        assert_eq!(calls.replace(vec![]),
+                   &[Call::Write(8), Call::SyncData, Call::Write(8), Call::SyncData]);
+
+        assert_eq!(journal.snapshot().marks().count(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_commit_if() -> Result<(), JournalError> {
+        let fd = tempfile()?;
+        let mut a = JournalMut::create_from_fd(fd.try_clone()?, ())?;
+        let mut b = JournalMut::<()>::open_fd(fd.try_clone()?)?;
+
+        a.commit_if(None, &1u8)?;
+        match b.commit_if(None, &2u8) {
+            Err(JournalError::Conflict { expected: None, actual: Some(1) }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        // Rebased on the new tip
+        b.commit_if(Some(1), &2u8)?;
+        match a.commit_if(Some(1), &3u8) {
+            Err(JournalError::Conflict { expected: Some(1), actual: Some(3) }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        let snapshot = Journal::<()>::open_fd(&fd)?;
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), &[1, 3]);
+        assert_eq!(*snapshot.latest_root::<u8>().unwrap().unwrap().get(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_lock() -> Result<(), JournalError> {
+        use std::thread;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        let mut journal = JournalMut::create(&path, ())?;
+        match JournalMut::<()>::open(&path, true) {
+            Err(JournalError::Locked) => {},
+            r => panic!("{:?}", r),
+        }
+        match Journal::<()>::open(&path) {
+            Err(JournalError::Locked) => {},
+            r => panic!("{:?}", r),
+        }
+
+        // Readers that don't lock run alongside the writer, and it alongside them.
+        let reader = Journal::<()>::open_with_lock(&path, Lock::None)?;
+        journal.write_root(&1u8)?;
+        reader.refresh()?;
+        assert_eq!(*reader.latest_root::<u8>().unwrap().unwrap().get(), 1);
+        drop(journal);
+        let journal = JournalMut::<()>::open(&path, true)?;
+        drop(journal);
+
+        // Readers share; a writer has to wait for all of them, including snapshots.
+        let reader = Journal::<()>::open(&path)?;
+        let snapshot = Journal::<()>::open(&path)?.clone();
+        match JournalMut::<()>::open(&path, true) {
+            Err(JournalError::Locked) => {},
+            r => panic!("{:?}", r),
+        }
+        drop(reader);
+        match JournalMut::<()>::open(&path, true) {
+            Err(JournalError::Locked) => {},
+            r => panic!("{:?}", r),
+        }
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(snapshot);
+        });
+        JournalMut::<()>::open_with_lock(&path, true, Lock::Wait)?;
+        handle.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn journal_follow() -> Result<(), JournalError> {
+        use std::thread;
+
+        let fd = tempfile()?;
+        let mut journal = JournalMut::create_from_fd(fd.try_clone()?, ())?;
+        journal.write_root(&1u8)?;
+
+        let reader = Journal::<()>::open_fd(&fd)?;
+        let frozen = reader.clone();
+        assert_eq!(reader.marks().count(), 1);
+
+        let writer = thread::spawn(move || -> io::Result<()> {
+            for i in 2 ..= 4u8 {
+                thread::sleep(Duration::from_millis(10));
+                journal.write_root(&i)?;
+            }
+            Ok(())
+        });
+
+        let mut follow = reader.follow::<u8>();
+        let mut roots = vec![];
+        while roots.len() < 3 {
+            match follow.next() {
+                Some(r) => {
+                    let (mark, root) = r.unwrap();
+                    roots.push((mark, *root.get()));
+                },
+                None => {
+                    thread::sleep(Duration::from_millis(1));
+                    reader.refresh()?;
+                }
+            }
+        }
+        assert_eq!(roots, &[(3, 2), (5, 3), (7, 4)]);
+        writer.join().unwrap()?;
+
+        // Clones are refreshed independently.
+        assert_eq!(frozen.marks().count(), 1);
+        assert!(frozen.refresh()?);
+        assert!(!frozen.refresh()?);
+        assert_eq!(*frozen.latest_root::<u8>().unwrap().unwrap().get(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_torn_tail() -> Result<(), JournalError> {
+        let fd = tempfile()?;
+        let mut journal = JournalMut::create_from_fd(fd.try_clone()?, ())?;
+        journal.write_root(&42u8)?;
+        let committed_len = journal.snapshot().committed_len();
+        assert_eq!(committed_len, 32 + 16);
+        drop(journal);
+
+        // A commit that was killed part way through
+        (&fd).write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11])?;
+
+        let snapshot = Journal::<()>::open_fd(&fd)?;
+        assert_eq!(snapshot.uncommitted_len(), 11);
+        assert_eq!(*snapshot.latest_root::<u8>().unwrap().unwrap().get(), 42);
+
+        // Zeroed rather than truncated, as the snapshot has it mapped.
+        let mut journal = JournalMut::<()>::open_fd(fd.try_clone()?)?;
+        assert_eq!(fd.metadata()?.len(), committed_len as u64 + 11);
+        assert_eq!(&snapshot.file_bytes()[committed_len ..], &[0; 11]);
+        assert_eq!(journal.discard_uncommitted()?, 11);
+
+        journal.write_root(&43u8)?;
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), &[1, 3]);
+        assert_eq!(snapshot.uncommitted_len(), 0);
+        assert_eq!(*snapshot.latest_root::<u8>().unwrap().unwrap().get(), 43);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_torn_digest() -> Result<(), JournalError> {
+        let fd = tempfile()?;
+        let flags = JournalFlags { digests: true, ..Default::default() };
+        let mut journal = JournalMut::create_from_fd_with_flags(fd.try_clone()?, (), flags)?;
+        journal.write_root(&42u8)?;
+        let committed_len = journal.snapshot().committed_len();
+        drop(journal);
+
+        // A mark that reached the disk before the bytes it commits did.
+        (&fd).write_all(&[43, 0, 0, 0, 0, 0, 0, 0])?;
+        (&fd).write_all(&[0; digest::TRAILER_LEN])?;
+        (&fd).write_all(&(!13u64).to_le_bytes())?;
+
+        // The torn commit isn't visible to readers.
+        let snapshot = Journal::<()>::open_fd(&fd)?;
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), &[6]);
+        assert_eq!(*snapshot.latest_root::<u8>().unwrap().unwrap().get(), 42);
+        assert_eq!(snapshot.verify(), &[(6, CommitStatus::Intact), (13, CommitStatus::Corrupt)]);
+        assert_eq!(snapshot.committed_len(), committed_len);
+        assert_eq!(snapshot.uncommitted_len(), 56);
+
+        let mut journal = JournalMut::<()>::open_fd(fd.try_clone()?)?;
+        journal.write_root(&44u8)?;
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), &[6, 13]);
+        assert_eq!(snapshot.committed_len(), committed_len + 56);
+        assert_eq!(*snapshot.latest_root::<u8>().unwrap().unwrap().get(), 44);
+        Ok(())
     }
 
     #[test]
@@ -472,7 +1792,7 @@ mod tests {
         assert_eq!(marks.len(), 0);
 
         // but the reloaded one does
-        let snapshot = dbg!(journal.snapshot());
+        let snapshot = journal.snapshot();
 
         let marks = snapshot.marks().collect::<Vec<_>>();
         assert_eq!(marks, &[2]);
@@ -480,6 +1800,231 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn journal_write_root() -> io::Result<()> {
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+
+        let own = OffsetMut::alloc(42u8);
+        let offset = journal.write_root(&own)?;
+        assert_eq!(offset, 8);
+
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), &[2]);
+        assert_eq!(&snapshot.bytes()[JournalHeader::<()>::LEN ..],
+                   &[42,0,0,0,0,0,0,0,
+                     1,0,0,0,0,0,0,0,
+                     0xfd,0xff,0xff,0xff,0xff,0xff,0xff,0xff][..]);
+
+        // Clean offsets are reused rather than written again.
+        let clean: Own<u8, OffsetMut> = unsafe {
+            Own::new_unchecked(Fat::new(Offset::new(0).unwrap().into(), ()))
+        };
+        let own2 = OffsetMut::alloc(clean);
+        let offset = journal.write_root(&own2)?;
+        assert_eq!(offset, 32);
+
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.marks().collect::<Vec<_>>(), &[2, 5]);
+        assert_eq!(&snapshot.bytes()[JournalHeader::<()>::LEN + 24 ..],
+                   &[1,0,0,0,0,0,0,0,
+                     49,0,0,0,0,0,0,0,
+                     0xfa,0xff,0xff,0xff,0xff,0xff,0xff,0xff][..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn journal_save_root_chunked() -> io::Result<()> {
+        type Root<'p, 'v> = [Own<Le<u64>, OffsetMut<'p, 'v>>; 8];
+
+        // Words that would look like marks at nearby offsets, so items need padding.
+        let leaf = |i: u64| OffsetMut::alloc(Le::new(!(i * 3)));
+        let make_root = || -> Root {
+            [leaf(1), leaf(2), leaf(3), leaf(4), leaf(5), leaf(6), leaf(7), leaf(8)]
+        };
+
+        let mut expected = JournalMut::create_from_fd(tempfile()?, ())?;
+        expected.set_flush_threshold(usize::MAX);
+        expected.write_root(&make_root())?;
+
+        let fd = tempfile()?;
+        let probe = fd.try_clone()?;
+        let mut journal = JournalMut::create_from_fd(fd, ())?;
+        journal.set_flush_threshold(0);
+
+        let root = make_root();
+        let mut save = journal.save_root(&root)?;
+        let mut polls = 1;
+        let mut lens = vec![probe.metadata()?.len()];
+        while !save.poll(16)? {
+            polls += 1;
+            lens.push(probe.metadata()?.len());
+        }
+        let offset = save.commit()?;
+
+        // Each chunk was written out before the commit.
+        assert!(polls > 2, "{}", polls);
+        assert!(lens.windows(2).all(|w| w[0] < w[1]), "{:?}", lens);
+
+        assert_eq!(journal.snapshot().bytes(), expected.snapshot().bytes());
+
+        let snapshot = journal.snapshot();
+        let (own, pile) = snapshot.latest_root::<Root>().unwrap().unwrap().into_parts();
+        let root = own.get_in(&pile);
+        assert_eq!(root[7].get_in(&pile).get(), !24);
+        assert_eq!(offset, snapshot.root_offset(snapshot.marks().last().unwrap(), Root::BLOB_LEN).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_dedup() -> io::Result<()> {
+        type Root<'p, 'v> = [Own<u8, OffsetMut<'p, 'v>>; 4];
+        let make_root = || -> Root {
+            [OffsetMut::alloc(1u8), OffsetMut::alloc(2u8), OffsetMut::alloc(1u8), OffsetMut::alloc(2u8)]
+        };
+
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        journal.write_root(&make_root())?;
+        let len = journal.snapshot().uncommitted_len() + journal.snapshot().committed_len();
+
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        journal.set_dedup(true);
+        journal.write_root(&make_root())?;
+        assert_eq!(journal.snapshot().committed_len(), len - 2 * 8);
+
+        // Blobs from earlier commits are reused too.
+        let len = journal.snapshot().committed_len();
+        journal.write_root(&make_root())?;
+        assert_eq!(journal.snapshot().committed_len(), len + 4 * 8 + 8);
+
+        let snapshot = journal.snapshot();
+        let (own, pile) = snapshot.latest_root::<Root>().unwrap().unwrap().into_parts();
+        let root = own.get_in(&pile);
+        let leaves: Vec<u8> = root.iter().map(|leaf| *leaf.get_in(&pile)).collect();
+        assert_eq!(leaves, &[1, 2, 1, 2]);
+
+        // An abandoned save isn't remembered, as its blobs are discarded.
+        let new_leaf = OffsetMut::alloc(OffsetMut::alloc(3u8));
+        let mut save = journal.save_root(&new_leaf)?;
+        assert!(save.poll(usize::MAX)?);
+        drop(save);
+
+        // ...but blobs from earlier commits still are.
+        let len = journal.snapshot().committed_len();
+        journal.write_root(&make_root())?;
+        assert_eq!(journal.snapshot().committed_len(), len + 4 * 8 + 8);
+
+        journal.write_root(&new_leaf)?;
+        let snapshot = journal.snapshot();
+        let (own, pile) = snapshot.latest_root::<Own<Own<u8, OffsetMut>, OffsetMut>>().unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile).get_in(&pile), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_upgrade() -> io::Result<()> {
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        journal.write_root(&OffsetMut::alloc(1u8))?;
+
+        let old = journal.snapshot();
+        let (root, old_pile) = old.latest_root::<Own<u8, OffsetMut>>().unwrap().unwrap().into_parts();
+
+        journal.write_root(&OffsetMut::alloc(2u8))?;
+        let new = journal.snapshot();
+        let (_, new_pile) = new.latest_root::<Own<u8, OffsetMut>>().unwrap().unwrap().into_parts();
+
+        let upgrade = old_pile.upgrade_to(&new_pile).unwrap();
+        let root = upgrade.own(root);
+        assert_eq!(*root.get_in(&new_pile).get_in(&new_pile), 1);
+
+        // The old pile isn't a newer version of the new one.
+        assert!(new_pile.upgrade_to(&old_pile).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_compact() -> Result<(), JournalError> {
+        type Root<'p, 'v> = Own<Own<u8, OffsetMut<'p, 'v>>, OffsetMut<'p, 'v>>;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        let mut journal = JournalMut::create(&path, ())?;
+        for i in 0 .. 10u8 {
+            journal.write_root(&OffsetMut::alloc(OffsetMut::alloc(i)))?;
+        }
+
+        let journal2 = journal.compact::<Root>(&path)?;
+
+        // The old journal's file is gone, so it can't be committed to.
+        assert!(journal.write_root(&OffsetMut::alloc(OffsetMut::alloc(10u8))).is_err());
+        drop(journal);
+
+        // Just the latest root: the u8, the pointer to it, the root itself, and a mark.
+        assert_eq!(fs::metadata(&path)?.len(), 32 + 32);
+        assert!(!dir.path().join("journal.compact").exists());
+
+        // The new file is locked by the compacted journal, which can be written to.
+        match Journal::<()>::open(&path) {
+            Err(JournalError::Locked) => {},
+            r => panic!("{:?}", r),
+        }
+        let mut journal = journal2;
+        let snapshot = journal.snapshot();
+        let (own, pile) = snapshot.latest_root::<Root>().unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile).get_in(&pile), 9);
+
+        journal.write_root(&OffsetMut::alloc(OffsetMut::alloc(10u8)))?;
+
+        // The snapshot shares the locked file, so it holds the lock too.
+        drop(journal);
+        match JournalMut::<()>::open(&path, true) {
+            Err(JournalError::Locked) => {},
+            r => panic!("{:?}", r),
+        }
+        drop((own, pile));
+        drop(snapshot);
+
+        let journal = JournalMut::<()>::open(&path, true)?;
+        let snapshot = journal.snapshot();
+        let marks = snapshot.marks().collect::<Vec<_>>();
+        assert_eq!(marks.len(), 2);
+        let (own, pile) = snapshot.latest_root::<Root>().unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile).get_in(&pile), 10);
+
+        // A chosen set of roots gets a commit each.
+        let journal = journal.compact_roots::<Root>(&path, marks.iter().copied())?;
+        let snapshot = journal.snapshot();
+        assert_eq!(snapshot.marks().count(), 2);
+        let values = snapshot.marks().map(|mark| {
+            let (own, pile) = snapshot.root_at::<Root>(mark).unwrap().into_parts();
+            let value = *own.get_in(&pile).get_in(&pile).get_in(&pile);
+            value
+        }).collect::<Vec<_>>();
+        assert_eq!(values, &[9, 10]);
+
+        // Shared structure is only written once: the second commit is just the root and a mark.
+        let mark = snapshot.marks().last().unwrap();
+        let journal = journal.compact_roots::<Root>(&path, vec![mark, mark])?;
+        assert_eq!(fs::metadata(&path)?.len(), 32 + 32 + 16);
+        assert!(!journal.dedup());
+
+        // Not a mark
+        match journal.compact_roots::<Root>(&path, vec![0]) {
+            Err(JournalError::Root { mark: 0, .. }) => {},
+            r => panic!("{:?}", r),
+        }
+
+        // Compacting into another file leaves this one as it was.
+        let mut journal = journal;
+        let copy_path = dir.path().join("copy");
+        let copy = journal.compact::<Root>(&copy_path)?;
+        journal.write_root(&OffsetMut::alloc(OffsetMut::alloc(11u8)))?;
+        assert_eq!(journal.snapshot().marks().count(), 3);
+        assert_eq!(copy.snapshot().marks().count(), 1);
+        Ok(())
+    }
+
     #[test]
     fn journal_roots() -> io::Result<()> {
         let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
@@ -487,6 +2032,103 @@ mod tests {
         let snapshot = journal.snapshot();
         assert!(snapshot.roots().last().is_none());
 
+        journal.write_root(&42u8)?;
+        journal.write_root(&43u8)?;
+
+        let snapshot = journal.snapshot();
+        let roots = snapshot.roots().collect::<Vec<_>>();
+        assert_eq!(roots.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn journal_latest_root() -> io::Result<()> {
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+
+        let snapshot = journal.snapshot();
+        assert!(snapshot.latest_root::<u8>().unwrap().is_none());
+
+        journal.write_root(&42u8)?;
+        journal.write_root(&OffsetMut::alloc(43u8))?;
+
+        let snapshot = journal.snapshot();
+        let marks = snapshot.marks().collect::<Vec<_>>();
+
+        let bag = snapshot.root_at::<u8>(marks[0]).unwrap();
+        assert_eq!(*bag.get(), 42);
+
+        let bag = snapshot.latest_root::<Own<u8, OffsetMut>>().unwrap().unwrap();
+        let (own, pile) = bag.into_parts();
+        let inner = own.get_in(&pile);
+        assert_eq!(*inner.get_in(&pile), 43);
+
+        // Not a mark
+        assert!(snapshot.root_at::<u8>(marks[0] - 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn journal_history() -> io::Result<()> {
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+        assert_eq!(journal.snapshot().history::<u8>().count(), 0);
+
+        for i in 0 .. 5u8 {
+            journal.write_root(&OffsetMut::alloc(i))?;
+        }
+
+        let snapshot = journal.snapshot();
+        let history = snapshot.history::<Own<u8, OffsetMut>>();
+        assert_eq!(history.len(), 5);
+        let history = history.map(|r| {
+                                 let (mark, idx, bag) = r.unwrap();
+                                 let (own, pile) = bag.into_parts();
+                                 let value = *own.get_in(&pile).get_in(&pile);
+                                 (mark, idx, value)
+                             }).collect::<Vec<_>>();
+        assert_eq!(history, &[(14, 4, 4), (11, 3, 3), (8, 2, 2), (5, 1, 1), (2, 0, 0)]);
+
+        assert_eq!(snapshot.commit_mark(1), Some(5));
+        assert_eq!(snapshot.commit_mark(5), None);
+
+        let (own, pile) = snapshot.root_at_commit::<Own<u8, OffsetMut>>(2).unwrap().unwrap().into_parts();
+        assert_eq!(*own.get_in(&pile).get_in(&pile), 2);
+        assert!(snapshot.root_at_commit::<u8>(5).unwrap().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn journal_root_invalid() -> io::Result<()> {
+        let mut journal = JournalMut::create_from_fd(tempfile()?, ())?;
+
+        let mut writer = JournalWriter::new(&mut journal)?;
+        let mut entry = writer.write_item(1);
+        entry.write_bytes(&[2]);
+        entry.finish();
+        writer.commit()?;
+
+        let snapshot = journal.snapshot();
+        assert!(snapshot.latest_root::<bool>().is_err());
+        assert_eq!(*snapshot.latest_root::<u8>().unwrap().unwrap().get(), 2);
+
+        // A valid root pointing to an invalid blob
+        let mut writer = JournalWriter::new(&mut journal)?;
+        let mut entry = writer.write_item(1);
+        entry.write_bytes(&[2]);
+        let offset = entry.finish().get() as u64;
+        let mut entry = writer.write_item(8);
+        entry.write_bytes(&(offset << 1 | 1).to_le_bytes());
+        entry.finish();
+        writer.commit()?;
+
+        let snapshot = journal.snapshot();
+        match snapshot.latest_root::<Own<bool, OffsetMut>>() {
+            Err(RootError::Invalid { .. }) => {},
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+        assert!(snapshot.latest_root::<Own<u8, OffsetMut>>().is_ok());
+
         Ok(())
     }
 }
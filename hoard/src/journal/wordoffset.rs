@@ -14,7 +14,7 @@ impl WordOffset {
     pub const WORD: Self = WordOffset(mem::size_of::<Word>());
 
     /// Creates a `WordOffset` by aligning an offset.
-    pub fn align(offset: usize) -> Self {
+    pub const fn align(offset: usize) -> Self {
         let size = mem::size_of::<Word>() as usize;
         let aligned = ((offset + size - 1) / size) * size;
         Self(aligned)
@@ -26,7 +26,7 @@ impl WordOffset {
         aligned - offset
     }
 
-    pub fn get(self) -> usize {
+    pub const fn get(self) -> usize {
         self.0
     }
 }
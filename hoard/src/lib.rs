@@ -59,11 +59,15 @@ pub mod heap;
 
 pub mod bag;
 
+pub mod diff;
+
 pub mod offset;
 pub mod pile;
 
 pub mod journal;
 
+pub mod db;
+
 pub use leint::Le;
 
 pub mod prelude {
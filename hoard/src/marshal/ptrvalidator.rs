@@ -1,3 +1,6 @@
+//! Superseded by `pile::validate`, which validates pointers by walking them with a `SavePtr`, so
+//! any type that can be saved can also be validated deeply.
+
 pub trait PtrValidator<Z> {
     type Error;
 }
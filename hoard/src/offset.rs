@@ -20,6 +20,7 @@ use crate::save::*;
 use crate::primitive::*;
 use crate::ptr::*;
 
+use crate::refs::Ref;
 use crate::heap::HeapPtr;
 use crate::pile::Pile;
 
@@ -86,6 +87,13 @@ impl<'p, 'v> From<Offset<'p, 'v>> for OffsetMut<'p, 'v> {
     }
 }
 
+impl cmp::PartialEq for Offset<'_, '_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+impl cmp::Eq for Offset<'_, '_> {}
+
 impl cmp::PartialEq<usize> for Offset<'_, '_> {
     fn eq(&self, other: &usize) -> bool {
         self.get() == *other
@@ -97,11 +105,23 @@ impl cmp::PartialEq<Offset<'_, '_>> for usize {
     }
 }
 
+impl Offset<'static, 'static> {
+    /// Creates an offset that doesn't point into any particular pile.
+    ///
+    /// Offsets with the lifetimes of a pile only come from the pile itself, eg from
+    /// `TryPile::offset()`, so they can't be made up for a pile they don't belong to.
+    #[inline(always)]
+    pub fn new(offset: usize) -> Option<Self> {
+        Self::new_any(offset)
+    }
+}
+
 impl<'p, 'v> Offset<'p, 'v> {
     pub const MAX: usize = (1 << 62) - 1;
 
+    /// Like `new()`, but for any lifetimes; it's up to the caller to brand the offset correctly.
     #[inline(always)]
-    pub fn new(offset: usize) -> Option<Self> {
+    pub(crate) fn new_any(offset: usize) -> Option<Self> {
         let offset = offset as u64;
         offset.checked_shl(1).map(|offset|
             Self {
@@ -112,7 +132,7 @@ impl<'p, 'v> Offset<'p, 'v> {
     }
 
     #[inline(always)]
-    pub fn cast<'p2, 'v2>(&self) -> Offset<'p2, 'v2> {
+    pub(crate) fn cast<'p2, 'v2>(&self) -> Offset<'p2, 'v2> {
         Offset {
             marker: PhantomData,
             raw: self.raw,
@@ -124,9 +144,10 @@ impl<'p, 'v> Offset<'p, 'v> {
         (self.raw.get().get() >> 1) as usize
     }
 
+    /// An offset past the end of any pile, which can be given any brand as it never points into one.
     #[inline(always)]
-    pub fn dangling() -> Self {
-        Self::new(Self::MAX).unwrap()
+    pub(crate) fn dangling() -> Self {
+        Self::new_any(Self::MAX).unwrap()
     }
 
     pub fn to_static(&self) -> Offset<'static, 'static> {
@@ -138,15 +159,30 @@ impl<'p, 'v> Offset<'p, 'v> {
 }
 
 #[derive(Debug, Error)]
-#[error("FIXME")]
+#[error("invalid offset: untagged, or larger than the maximum offset")]
 pub struct ValidateBlobOffsetError;
 
+/// Validates the raw bytes of a persistent offset.
+///
+/// The tag bit must be set, as heap pointers are never persisted.
+fn validate_raw_offset(buf: &[u8]) -> Result<(), ValidateBlobOffsetError> {
+    let raw = u64::from_le_bytes(buf.try_into().unwrap());
+
+    if raw & 1 == 1 && (raw >> 1) as usize <= Offset::MAX {
+        Ok(())
+    } else {
+        Err(ValidateBlobOffsetError)
+    }
+}
+
 impl<'p, 'v> ValidateBlob for Offset<'p, 'v> {
     const BLOB_LEN: usize = mem::size_of::<Self>();
     type Error = ValidateBlobOffsetError;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        todo!()
+        let blob = Blob::from(blob);
+        validate_raw_offset(blob.as_bytes())?;
+        unsafe { Ok(blob.assume_valid()) }
     }
 }
 
@@ -155,19 +191,21 @@ impl<'p, 'v, A> ValidateBlob for OffsetMut<'p, 'v, A> {
     type Error = ValidateBlobOffsetError;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        todo!()
+        let blob = Blob::from(blob);
+        validate_raw_offset(blob.as_bytes())?;
+        unsafe { Ok(blob.assume_valid()) }
     }
 }
 
 impl<Q: Ptr> Decode<Q> for Offset<'_, '_> {
     fn decode_blob(blob: BlobDecoder<Q, Self>) -> Self {
-        todo!()
+        *blob.to_value()
     }
 }
 
 impl<Q: Ptr> Decode<Q> for OffsetMut<'_, '_> {
     fn decode_blob(blob: BlobDecoder<Q, Self>) -> Self {
-        todo!()
+        *blob.to_value()
     }
 }
 
@@ -200,6 +238,15 @@ impl<'p, 'v, A> OffsetMut<'p, 'v, A> {
         }
     }
 
+    /// Changes the version of the pile this points into.
+    #[inline(always)]
+    pub(crate) fn cast_version<'v2>(self) -> OffsetMut<'p, 'v2, A> {
+        OffsetMut {
+            marker: PhantomData,
+            inner: self.inner.cast(),
+        }
+    }
+
     #[inline(always)]
     pub fn get_offset(&self) -> Option<Offset<'p, 'v>> {
         match self.kind() {
@@ -374,11 +421,11 @@ impl<'p, 'v> SavePtr for ShallowDumper<'p, 'v> {
     type Target = Offset<'p, 'v>;
     type Error = !;
 
-    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, &'a T>
-        where T: Pointee
+    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'a, T>>
+        where T: Load<Self::Source>
     {
         match ptr.try_get_dirty_unchecked::<T>(metadata) {
-            Ok(r) => Err(r),
+            Ok(r) => Err(Ref::Ref(r)),
             Err(offset) => Ok(offset.cast())
         }
     }
@@ -386,7 +433,7 @@ impl<'p, 'v> SavePtr for ShallowDumper<'p, 'v> {
     fn try_save_ptr(mut self, value: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
         let offset = self.initial_offset
                          .checked_add(self.written.len())
-                         .and_then(Offset::new)
+                         .and_then(Offset::new_any)
                          .expect("overflow");
 
         let written = mem::replace(&mut self.written, vec![]);
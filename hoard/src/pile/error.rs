@@ -1,81 +1,152 @@
-use std::error::Error;
-use std::fmt::Debug;
+//! Errors returned when blobs can't be read from a pile.
 
-use thiserror::Error;
+use std::any::type_name;
+use std::error;
+use std::fmt;
 
-use crate::pointee::Pointee;
+use thiserror::Error;
 
 use super::*;
 
-#[derive(Debug, Error)]
-#[error("fixme")]
-pub enum GetBlobError<LayoutError: Debug> {
-    OutOfRange,
-    Layout(LayoutError),
-}
-
-#[derive(Debug, Error)]
-#[error("fixme")]
-pub enum GetValidBlobError<LayoutError: Debug, ValidateError: Debug> {
-    Blob(GetBlobError<LayoutError>),
-    Validate(ValidateError),
-}
-
-impl<L: Error, V: Error> From<GetBlobError<L>> for GetValidBlobError<L, V> {
-    fn from(err: GetBlobError<L>) -> Self {
-        Self::Blob(err)
-    }
-}
-
-/*
-#[derive(Debug, Error)]
-#[error("FIXME")]
-pub struct Error<
-    P: Debug,
-    M: ?Sized + Debug,
-    L: Debug,
-    V: Debug,
->
-{
-    pile: P,
-    offset: Offset,
+/// An error getting a blob from a pile.
+///
+/// Records where the blob was expected, what it was expected to be, and why it couldn't be
+/// returned. The `Display` impl describes the blob; the underlying cause is available via
+/// `source()`, which in turn returns the layout or validation error, if any.
+#[derive(Debug)]
+pub struct Error<L, V> {
+    offset: Offset<'static, 'static>,
+    type_name: &'static str,
+    metadata: String,
+    pile_len: usize,
     kind: ErrorKind<L, V>,
-    metadata: M,
 }
 
+/// Why a blob couldn't be returned.
 #[derive(Debug)]
 pub enum ErrorKind<L, V> {
+    /// The metadata doesn't describe a valid layout.
     Layout(L),
-    OutOfRange(usize),
+
+    /// The blob extends past the end of the pile.
+    OutOfRange {
+        blob_len: usize,
+    },
+
+    /// The blob's bytes are invalid.
     Validate(V),
 }
 
-impl<P, M, L, V> Error<P, M, L, V>
-where P: Debug, M: Debug, L: Debug, V: Debug
-{
-    pub fn new<Z>(pile: P, offset: &Offset<Z>, metadata: M, kind: ErrorKind<L, V>) -> Self {
+/// An error getting an unvalidated blob.
+pub type GetBlobError<L> = Error<L, !>;
+
+/// An error getting a validated blob.
+pub type GetValidBlobError<L, V> = Error<L, V>;
+
+/// An error extending a pile to a newer version.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum ExtendError {
+    /// The new buffer is shorter than the pile.
+    #[error("new version is {new_len} bytes, shorter than the {old_len} byte pile")]
+    Shorter {
+        old_len: usize,
+        new_len: usize,
+    },
+
+    /// The new buffer doesn't start with the pile's bytes.
+    #[error("new version differs from the pile at byte {idx}")]
+    Differs {
+        idx: usize,
+    },
+}
+
+impl<L, V> Error<L, V> {
+    pub(super) fn new<T: ?Sized + Pointee>(
+        offset: Offset,
+        metadata: T::Metadata,
+        pile_len: usize,
+        kind: ErrorKind<L, V>
+    ) -> Self {
         Self {
-            pile,
             offset: offset.to_static(),
-            metadata: metadata,
+            type_name: type_name::<T>(),
+            metadata: format!("{:?}", metadata),
+            pile_len,
             kind,
         }
     }
-}
 
-/*
-    pub fn offset(&self) -> &Offset {
-        &self.offset
+    /// The offset the blob was expected at.
+    pub fn offset(&self) -> Offset<'static, 'static> {
+        self.offset
+    }
+
+    /// The name of the type the blob was expected to be.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
     }
 
-    pub fn metadata(&self) -> &M {
+    /// The blob's metadata, formatted with `Debug`.
+    pub fn metadata(&self) -> &str {
         &self.metadata
     }
 
+    /// The length of the pile, in bytes.
+    pub fn pile_len(&self) -> usize {
+        self.pile_len
+    }
+
     pub fn kind(&self) -> &ErrorKind<L, V> {
         &self.kind
     }
 }
-*/
 
-*/
+impl<L> GetBlobError<L> {
+    /// Converts an unvalidated blob error into a validated blob error.
+    pub fn into_valid<V>(self) -> GetValidBlobError<L, V> {
+        Error {
+            offset: self.offset,
+            type_name: self.type_name,
+            metadata: self.metadata,
+            pile_len: self.pile_len,
+            kind: match self.kind {
+                ErrorKind::Layout(err) => ErrorKind::Layout(err),
+                ErrorKind::OutOfRange { blob_len } => ErrorKind::OutOfRange { blob_len },
+                ErrorKind::Validate(never) => match never {},
+            },
+        }
+    }
+}
+
+impl<L, V> fmt::Display for Error<L, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can't get `{}` blob at offset {} (metadata {}, pile length {})",
+               self.type_name, self.offset.get(), self.metadata, self.pile_len)
+    }
+}
+
+impl<L: error::Error + 'static, V: error::Error + 'static> error::Error for Error<L, V> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+impl<L, V> fmt::Display for ErrorKind<L, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Layout(_) => write!(f, "invalid layout"),
+            ErrorKind::OutOfRange { blob_len } => write!(f, "blob of length {} runs past end of pile", blob_len),
+            ErrorKind::Validate(_) => write!(f, "blob failed validation"),
+        }
+    }
+}
+
+impl<L: error::Error + 'static, V: error::Error + 'static> error::Error for ErrorKind<L, V> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ErrorKind::Layout(err) => Some(err),
+            ErrorKind::OutOfRange { .. } => None,
+            ErrorKind::Validate(err) => Some(err),
+        }
+    }
+}
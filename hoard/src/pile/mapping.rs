@@ -1,34 +1,68 @@
-use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
-use std::marker::PhantomData;
+//! Memory mappings that grow in place as the underlying file is appended to.
+
+use std::cmp;
+use std::fs::File;
+use std::io;
 use std::slice;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use memmap::{Mmap, MmapOptions};
 
+/// A read-only mapping of an append-only file.
+///
+/// More address space is reserved than the file needs, so when the file grows the visible length
+/// can be extended without moving the mapping. If the file outgrows the reservation a larger
+/// mapping is made, but the old one is kept until the `Mapping` is dropped. Either way, slices
+/// returned by `as_bytes()` stay valid for as long as the `Mapping` is borrowed, even while other
+/// threads call `refresh()`.
 #[derive(Debug)]
-pub struct Mapping<'a, T: ?Sized> {
+pub struct Mapping {
     ptr: AtomicPtr<u8>,
     len: AtomicUsize,
-    inner: &'a M,
+    fd: File,
+
+    /// Every mapping made so far, the current one last. Also serializes `refresh()`.
+    mmaps: Mutex<Vec<Mmap>>,
 }
 
-impl<'a, T> Mapping<'a, T> {
-    pub fn new(inner: &'a T) -> Self
-        where M: Borrow<[u8]>
-    {
-        unsafe {
-            Self::new_unchecked(inner.borrow(), inner)
-        }
+impl Mapping {
+    /// The least address space reserved by a mapping.
+    const MIN_RESERVE: usize = 1 << 30;
+
+    /// Maps `fd`, which must be open for reading.
+    ///
+    /// A duplicate of the file descriptor is kept, to map the file again if it outgrows the
+    /// reservation.
+    pub fn new(fd: &File) -> io::Result<Self> {
+        let fd = fd.try_clone()?;
+        let len = file_len(&fd)?;
+        let mmap = Self::map(&fd, len)?;
+
+        Ok(Self {
+            ptr: AtomicPtr::new(mmap.as_ptr() as *mut u8),
+            len: AtomicUsize::new(len),
+            fd,
+            mmaps: Mutex::new(vec![mmap]),
+        })
     }
 
-    pub unsafe fn new_unchecked(slice: *const [u8], inner: &'p M) -> Self {
-        Self {
-            ptr: (slice.as_ptr() as *mut u8).into(),
-            len: slice.len().into(),
-            inner,
-        }
+    fn map(fd: &File, len: usize) -> io::Result<Mmap> {
+        let reserve = cmp::max(len.saturating_mul(2), Self::MIN_RESERVE);
+
+        // Mapping past the end of the file is fine, so long as those pages aren't touched until
+        // the file has grown to cover them.
+        unsafe { MmapOptions::new().len(reserve).map(fd) }
     }
-}
 
-impl<'a, T: ?Sized> Mapping<'a, T> {
-    pub fn as_bytes(&self) -> &'a [u8] {
+    /// The length of the longest slice `as_bytes()` has returned, or will return.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        // The pointer is stored before the length, so loading the length first means the pointer
+        // is at least as new, and the mapping it points to covers the length.
         let len = self.len.load(Ordering::Acquire);
         let ptr = self.ptr.load(Ordering::Acquire);
 
@@ -36,47 +70,70 @@ impl<'a, T: ?Sized> Mapping<'a, T> {
             slice::from_raw_parts(ptr, len)
         }
     }
-}
 
-/*
-    pub unsafe fn extend_unchecked<'b>(&'a mut self, new_slice: &'b [u8]) -> &'a Mapping<'b>
-        //where 'a: 'b
-    {
-        let cur_ptr = self.ptr.load(Ordering::Relaxed);
-        let cur_len = self.len.load(Ordering::Relaxed);
-
-        self.ptr.compare_exchange(
-            cur_ptr,
-            new_slice.as_ptr() as *mut u8,
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-        ).expect("exclusive write");
-
-        self.len.compare_exchange(
-            cur_len,
-            new_slice.len(),
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-        ).expect("exclusive exclusive write");
-
-        self
+    /// Extends the mapping to cover the file as it is now, returning the file's length.
+    ///
+    /// The length of the mapping itself never shrinks: if the file has been truncated, the bytes
+    /// past its new end must not be accessed.
+    pub fn refresh(&self) -> io::Result<usize> {
+        let mut mmaps = self.mmaps.lock().unwrap();
+
+        let file_len = file_len(&self.fd)?;
+        if file_len > self.len.load(Ordering::Acquire) {
+            if file_len > mmaps.last().expect("at least one mapping").len() {
+                let mmap = Self::map(&self.fd, file_len)?;
+                self.ptr.store(mmap.as_ptr() as *mut u8, Ordering::Release);
+                mmaps.push(mmap);
+            }
+            self.len.store(file_len, Ordering::Release);
+        }
+        Ok(file_len)
     }
 }
-    */
 
-/*
-#[derive(Debug)]
-pub struct Mapping2<M: ?Sized>(M);
+fn file_len(fd: &File) -> io::Result<usize> {
+    let len = fd.metadata()?.len();
+    if len > isize::MAX as u64 {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "file too large to map"))
+    } else {
+        Ok(len as usize)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::io::Write;
+
+    use tempfile::tempfile;
+
     #[test]
-    fn test() {
-        let mapping = Mapping2(vec![1u8]);
+    fn refresh() -> io::Result<()> {
+        let mut fd = tempfile()?;
+        fd.write_all(&[1, 2, 3])?;
+
+        let mapping = Mapping::new(&fd)?;
+        let before = mapping.as_bytes();
+        assert_eq!(before, &[1, 2, 3]);
+
+        fd.write_all(&[4, 5])?;
+        assert_eq!(mapping.as_bytes(), &[1, 2, 3]);
+        assert_eq!(mapping.refresh()?, 5);
+        assert_eq!(mapping.as_bytes(), &[1, 2, 3, 4, 5]);
+        assert_eq!(before, &[1, 2, 3]);
+
+        // Outgrow the reservation
+        fd.set_len(Mapping::MIN_RESERVE as u64 + 1)?;
+        assert_eq!(mapping.refresh()?, Mapping::MIN_RESERVE + 1);
+        assert_eq!(&mapping.as_bytes()[.. 5], &[1, 2, 3, 4, 5]);
+        assert_eq!(mapping.mmaps.lock().unwrap().len(), 2);
+        assert_eq!(before, &[1, 2, 3]);
 
-        let dyn_mapping: &Mapping2<dyn std::fmt::Debug> = &mapping;
+        // Truncation doesn't shrink the mapping.
+        fd.set_len(5)?;
+        assert_eq!(mapping.refresh()?, 5);
+        assert_eq!(mapping.len(), Mapping::MIN_RESERVE + 1);
+        Ok(())
     }
 }
-*/
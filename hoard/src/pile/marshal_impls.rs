@@ -6,10 +6,13 @@ use crate::save::*;
 
 impl ValidateBlob for TryPile<'_, '_> {
     const BLOB_LEN: usize = 0;
-    type Error = !;
+    type Error = BlobError;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        unsafe { Ok(blob.finish()) }
+        if blob.is_pileless() {
+            return Err(BlobError::EmbeddedPile);
+        }
+        unsafe { blob.finish() }
     }
 }
 
@@ -29,10 +32,13 @@ impl<Q, R> Encode<Q, R> for TryPile<'_, '_> {
 
 impl ValidateBlob for Pile<'_, '_> {
     const BLOB_LEN: usize = 0;
-    type Error = !;
+    type Error = BlobError;
 
     fn validate_blob<'a>(blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        unsafe { Ok(blob.finish()) }
+        if blob.is_pileless() {
+            return Err(BlobError::EmbeddedPile);
+        }
+        unsafe { blob.finish() }
     }
 }
 
@@ -30,7 +30,15 @@ use crate::save::*;
 use crate::blob::*;
 
 pub mod error;
-use self::error::*;
+use self::error::{Error, ErrorKind, ExtendError, GetBlobError, GetValidBlobError};
+
+pub mod mapping;
+
+pub mod validate;
+
+pub mod read;
+
+pub mod segment;
 
 mod marshal_impls;
 
@@ -52,33 +60,91 @@ impl<'p> Default for TryPile<'p, 'static> {
     }
 }
 
+impl<'v> TryPile<'_, 'v> {
+    /// Creates a new `TryPile` from a slice.
+    ///
+    /// The pile gets a unique `'p` lifetime, so offsets into it can't be used with any other pile.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use hoard::pile::TryPile;
+    /// # use hoard::offset::Offset;
+    /// TryPile::new(&[42u8], |pile| {
+    ///     let blob = pile.get_blob::<u8>(pile.offset(0).unwrap(), ()).unwrap();
+    ///     assert_eq!(blob.as_bytes(), &[42]);
+    /// })
+    /// ```
+    pub fn new<R>(buf: &'v [u8], f: impl for<'p> FnOnce(TryPile<'p, 'v>) -> R) -> R {
+        Unique::new(buf, |buf| f(TryPile::from(buf)))
+    }
+}
+
+impl<'p, 'v> From<Unique<'p, &'v [u8]>> for TryPile<'p, 'v> {
+    fn from(buf: Unique<'p, &'v [u8]>) -> Self {
+        // Safe as no other buffer has the lifetime 'p
+        unsafe { Self::new_unchecked(Unique::into_inner(buf)) }
+    }
+}
+
 impl<'p, 'v> TryPile<'p, 'v> {
     pub unsafe fn new_unchecked(buf: &'v [u8]) -> Self {
         Self { marker: PhantomData, buf, }
     }
 
+    /// Returns an offset into this pile, or `None` if `offset` is past the end.
+    pub fn offset(&self, offset: usize) -> Option<Offset<'p, 'v>> {
+        if offset <= self.buf.len() {
+            Offset::new_any(offset)
+        } else {
+            None
+        }
+    }
+
     pub fn get_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
         -> Result<Blob<'v, T>, GetBlobError<T::LayoutError>>
         where T: BlobLen
     {
+        let err = |kind| Error::new::<T>(offset, metadata, self.buf.len(), kind);
+
         let blob_len = T::try_blob_len(metadata)
-                         .map_err(GetBlobError::Layout)?;
+                         .map_err(|e| err(ErrorKind::Layout(e)))?;
 
         let start = offset.get();
         start.checked_add(blob_len)
              .and_then(|end| self.buf.get(start .. end))
              .map(|slice| unsafe { Blob::new_unchecked(slice, metadata) })
-             .ok_or(GetBlobError::OutOfRange)
+             .ok_or_else(|| err(ErrorKind::OutOfRange { blob_len }))
     }
 
     pub fn get_valid_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
         -> Result<ValidBlob<'v, T>, GetValidBlobError<T::LayoutError, T::Error>>
         where T: ValidateBlobPtr
     {
-        let blob = self.get_blob::<T>(offset, metadata)?;
+        self.validate_blob_with::<T>(offset, metadata, BlobValidator::from)
+    }
+
+    /// Like `get_valid_blob()`, but also rejects blobs that aren't canonically encoded.
+    pub fn get_canonical_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<ValidBlob<'v, T>, GetValidBlobError<T::LayoutError, T::Error>>
+        where T: ValidateBlobPtr
+    {
+        self.validate_blob_with::<T>(offset, metadata, BlobValidator::new_canonical)
+    }
+
+    fn validate_blob_with<T: ?Sized>(
+        &self,
+        offset: Offset<'p, 'v>,
+        metadata: T::Metadata,
+        validator: impl FnOnce(Blob<'v, T>) -> BlobValidator<'v, T>,
+    ) -> Result<ValidBlob<'v, T>, GetValidBlobError<T::LayoutError, T::Error>>
+        where T: ValidateBlobPtr
+    {
+        let blob = self.get_blob::<T>(offset, metadata)
+                       .map_err(GetBlobError::into_valid)?;
 
-        T::validate_blob_ptr(blob.into())
-          .map_err(GetValidBlobError::Validate)
+        T::validate_blob_ptr(validator(blob))
+          .map_err(|e| Error::new::<T>(offset, metadata, self.buf.len(), ErrorKind::Validate(e)))
     }
 
     pub unsafe fn extend_unchecked<'v2>(&self, new_buf: &'v2 [u8]) -> TryPile<'p, 'v2>
@@ -88,6 +154,53 @@ impl<'p, 'v> TryPile<'p, 'v> {
         TryPile::new_unchecked(new_buf)
     }
 
+    /// Extends the pile to a newer version, such as the mapping of a journal after a commit.
+    ///
+    /// `new_buf` must start with the bytes of this pile, which is always checked. Returns the new
+    /// version, and an `Upgrade` that converts offsets into this version into offsets into the new
+    /// one:
+    ///
+    /// ```
+    /// # use hoard::pile::TryPile;
+    /// let new_buf = [1u8; 16];
+    /// TryPile::new(&[1u8; 8], |old| {
+    ///     TryPile::new(&[1u8; 16], |other| {
+    ///         let offset = old.offset(0).unwrap();
+    ///         let (new, upgrade) = old.extend(&new_buf).unwrap();
+    ///         new.get_blob::<u8>(upgrade.offset(offset), ()).unwrap();
+    ///         other.get_blob::<u8>(other.offset(0).unwrap(), ()).unwrap();
+    ///     })
+    /// })
+    /// ```
+    ///
+    /// The pile itself stays the same, so upgraded offsets still can't be used with other piles:
+    ///
+    /// ```compile_fail,E0521
+    /// # use hoard::pile::TryPile;
+    /// let new_buf = [1u8; 16];
+    /// TryPile::new(&[1u8; 8], |old| {
+    ///     TryPile::new(&[1u8; 16], |other| {
+    ///         let offset = old.offset(0).unwrap();
+    ///         let (new, upgrade) = old.extend(&new_buf).unwrap();
+    ///         new.get_blob::<u8>(upgrade.offset(offset), ()).unwrap();
+    ///         other.get_blob::<u8>(upgrade.offset(offset), ()).unwrap();
+    ///     })
+    /// })
+    /// ```
+    pub fn extend<'v2>(&self, new_buf: &'v2 [u8]) -> Result<(TryPile<'p, 'v2>, Upgrade<'p, 'v, 'v2>), ExtendError> {
+        check_extends(self.buf, new_buf)?;
+        let pile = unsafe { TryPile::new_unchecked(new_buf) };
+        Ok((pile, Upgrade::new()))
+    }
+
+    /// Checks that `newer` is a newer version of this pile.
+    ///
+    /// Returns an `Upgrade` that converts offsets into this version into offsets into `newer`.
+    pub fn upgrade_to<'v2>(&self, newer: &TryPile<'p, 'v2>) -> Result<Upgrade<'p, 'v, 'v2>, ExtendError> {
+        check_extends(self.buf, newer.buf)?;
+        Ok(Upgrade::new())
+    }
+
     pub fn save_to_vec<T: ?Sized>(&self, tip: &T) -> (Vec<u8>, Offset<'p, 'v>)
         where T: Save<OffsetMut<'p, 'v>, Offset<'p, 'v>>
     {
@@ -224,6 +337,63 @@ impl<'p, 'v> Pile<'p, 'v> {
     {
         Pile(self.0.extend_unchecked(new_buf))
     }
+
+    /// Like `TryPile::extend()`, returning a `Pile`.
+    pub fn extend<'v2>(&self, new_buf: &'v2 [u8]) -> Result<(Pile<'p, 'v2>, Upgrade<'p, 'v, 'v2>), ExtendError> {
+        let (pile, upgrade) = self.0.extend(new_buf)?;
+        Ok((Pile(pile), upgrade))
+    }
+}
+
+fn check_extends(old: &[u8], new: &[u8]) -> Result<(), ExtendError> {
+    if new.len() < old.len() {
+        Err(ExtendError::Shorter { old_len: old.len(), new_len: new.len() })
+    } else if new.as_ptr() == old.as_ptr() {
+        // Same memory, so the same bytes.
+        Ok(())
+    } else {
+        match old.iter().zip(new).position(|(a, b)| a != b) {
+            Some(idx) => Err(ExtendError::Differs { idx }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Converts pointers into one version of a pile into pointers into a newer version.
+///
+/// Created by `TryPile::extend()` and `TryPile::upgrade_to()`, which check that the newer version
+/// starts with the bytes of the older one, so everything an old offset points to is unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct Upgrade<'p, 'v, 'v2> {
+    marker: PhantomData<(
+                fn(&'p ()) -> &'p (),
+                fn(&'v ()) -> &'v (),
+                fn(&'v2 ()) -> &'v2 (),
+            )>,
+}
+
+impl<'p, 'v, 'v2> Upgrade<'p, 'v, 'v2> {
+    fn new() -> Self {
+        Self { marker: PhantomData }
+    }
+
+    pub fn offset(&self, offset: Offset<'p, 'v>) -> Offset<'p, 'v2> {
+        offset.cast()
+    }
+
+    /// Upgrades an `OffsetMut`; heap pointers are left as they are.
+    pub fn offset_mut(&self, offset: OffsetMut<'p, 'v>) -> OffsetMut<'p, 'v2> {
+        offset.cast_version()
+    }
+
+    /// Upgrades the pointer of an `Own`.
+    ///
+    /// Only the pointer itself is upgraded. Values behind it, including any pointers of their
+    /// own, are decoded with the new version when they're next loaded.
+    pub fn own<T: ?Sized + Pointee>(&self, own: Own<T, OffsetMut<'p, 'v>>) -> Own<T, OffsetMut<'p, 'v2>> {
+        let fat = own.into_inner();
+        unsafe { Own::new_unchecked(Fat::new(self.offset_mut(fat.raw), fat.metadata)) }
+    }
 }
 
 impl<'p, 'v> Get<Offset<'p, 'v>> for Pile<'p, 'v> {
@@ -320,7 +490,11 @@ impl<'p, 'v> Alloc for Pile<'p, 'v> {
 mod tests {
     use super::*;
 
+    use std::any::type_name;
+
+    use crate::Le;
     use crate::bag::Bag;
+    use crate::blob::impls::option::ValidateBlobOptionError;
 
     #[test]
     fn test() {
@@ -333,6 +507,72 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get_blob_errors() {
+        let chain = |err: &dyn std::error::Error| {
+            let mut msg = err.to_string();
+            let mut source = err.source();
+            while let Some(err) = source {
+                msg += &format!(": {}", err);
+                source = err.source();
+            }
+            msg
+        };
+
+        let buf = [2u8];
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+
+        let err = pile.get_valid_blob::<bool>(Offset::new(0).unwrap(), ()).unwrap_err();
+        assert_eq!(err.offset(), 0);
+        assert_eq!(err.pile_len(), 1);
+        assert!(matches!(err.kind(), ErrorKind::Validate(_)));
+        assert_eq!(chain(&err),
+                   "can't get `bool` blob at offset 0 (metadata (), pile length 1): blob failed validation: invalid bool blob");
+
+        let err = pile.get_valid_blob::<bool>(Offset::new(8).unwrap(), ()).unwrap_err();
+        assert_eq!(chain(&err),
+                   "can't get `bool` blob at offset 8 (metadata (), pile length 1): blob of length 1 runs past end of pile");
+
+        // Errors in fields are chained beneath the error for the value that contains them.
+        let buf = [1u8, 2, 3, 0];
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let err = pile.get_valid_blob::<Option<bool>>(Offset::new(0).unwrap(), ()).unwrap_err();
+        assert_eq!(chain(&err),
+                   "can't get `core::option::Option<bool>` blob at offset 0 (metadata (), pile length 4): \
+                    blob failed validation: invalid option value: invalid bool blob");
+        let err = pile.get_valid_blob::<Option<bool>>(Offset::new(2).unwrap(), ()).unwrap_err();
+        assert_eq!(chain(&err),
+                   "can't get `core::option::Option<bool>` blob at offset 2 (metadata (), pile length 4): \
+                    blob failed validation: invalid option discriminant 3");
+
+        let buf = [0u8; 8];
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let err = pile.get_valid_blob::<Own<u8, Offset>>(Offset::new(0).unwrap(), ()).unwrap_err();
+        assert_eq!(chain(&err),
+                   format!("can't get `{}` blob at offset 0 (metadata (), pile length 8): \
+                            blob failed validation: invalid pointer: \
+                            invalid offset: untagged, or larger than the maximum offset",
+                           type_name::<Own<u8, Offset>>()));
+    }
+
+    #[test]
+    fn get_canonical_blob() {
+        let buf = [0u8, 0, 0, 0, 1];
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let offset = Offset::new(0).unwrap();
+
+        assert!(pile.get_valid_blob::<Option<Le<u32>>>(offset, ()).is_ok());
+        let err = pile.get_canonical_blob::<Option<Le<u32>>>(offset, ()).unwrap_err();
+        match err.kind() {
+            ErrorKind::Validate(ValidateBlobOptionError::Blob(BlobError::Padding { start: 4, end: 5 })) => {},
+            r => panic!("{:?}", r),
+        }
+
+        let buf = [0u8; 5];
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        assert!(pile.get_canonical_blob::<Option<Le<u32>>>(offset, ()).is_ok());
+    }
+
     #[test]
     fn test_alloc() {
         let pile = TryPile::default();
@@ -356,6 +596,26 @@ mod tests {
         bag2.get();
     }
 
+    #[test]
+    fn extend() {
+        let buf1 = [1u8, 2];
+        let pile1 = unsafe { Pile::new_unchecked(&buf1) };
+        let own1: Own<u8, OffsetMut> = unsafe { Own::new_unchecked(Fat::new(Offset::new(1).unwrap().into(), ())) };
+
+        let buf2 = [1u8, 2, 3];
+        let (pile2, upgrade) = pile1.extend(&buf2).unwrap();
+        let own2 = upgrade.own(own1);
+        assert_eq!(*own2.get_in(&pile2), 2);
+
+        // Same buffer, so the same memory.
+        assert!(pile2.upgrade_to(&pile2).is_ok());
+
+        assert_eq!(pile1.extend(&buf2[.. 1]).unwrap_err(),
+                   ExtendError::Shorter { old_len: 2, new_len: 1 });
+        assert_eq!(pile1.extend(&[1, 3, 3]).unwrap_err(),
+                   ExtendError::Differs { idx: 1 });
+    }
+
     #[test]
     fn test_extend() {
         let pile1 = Pile::default();
@@ -0,0 +1,328 @@
+//! Piles read on demand, rather than memory-mapped.
+//!
+//! A `ReadPile` reads blobs through `Read + Seek`, for files that can't or shouldn't be mapped,
+//! such as those on network filesystems or very large cold archives. Reads go through a bounded
+//! cache of fixed-size pages, and since there's no slice to borrow from, values are always
+//! returned as `Ref::Owned`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+
+use super::*;
+
+/// A pile read through `Read + Seek`.
+///
+/// Like a `TryPile`, blobs are validated as they're loaded. There's no slice for a decoded `Pile`
+/// to borrow, so values that embed their pile, such as a `Bag`, fail validation with
+/// `BlobError::EmbeddedPile`.
+#[derive(Debug)]
+pub struct ReadPile<'p, 'v, R> {
+    marker: PhantomData<(fn(&'p ()) -> &'p (), &'v ())>,
+    len: usize,
+    inner: RefCell<Inner<R>>,
+}
+
+#[derive(Debug)]
+struct Inner<R> {
+    reader: R,
+    cache: PageCache,
+    hits: u64,
+    misses: u64,
+}
+
+/// A bounded cache of pages, evicting the least recently used.
+#[derive(Debug)]
+struct PageCache {
+    page_size: usize,
+    max_pages: usize,
+    tick: u64,
+    /// Pages by index, along with when they were last used.
+    pages: HashMap<usize, (u64, Box<[u8]>)>,
+    /// Page indexes by when they were last used.
+    lru: BTreeMap<u64, usize>,
+}
+
+impl<'v, R: Read + Seek> ReadPile<'_, 'v, R> {
+    /// Creates a new `ReadPile`, with the default cache, and calls `f` with it.
+    ///
+    /// The pile gets a unique `'p` lifetime, so offsets into it can't be used with any other pile.
+    pub fn new<T>(reader: R, f: impl for<'p> FnOnce(ReadPile<'p, 'v, R>) -> T) -> io::Result<T> {
+        Unique::new(reader, |reader| {
+            ReadPile::from_unique(reader).map(f)
+        })
+    }
+}
+
+impl<'p, 'v, R: Read + Seek> ReadPile<'p, 'v, R> {
+    pub const DEFAULT_PAGE_SIZE: usize = 4096;
+    pub const DEFAULT_MAX_PAGES: usize = 256;
+
+    /// Creates a new `ReadPile` from a unique reader, with the default cache.
+    pub fn from_unique(reader: Unique<'p, R>) -> io::Result<Self> {
+        Self::with_cache(reader, Self::DEFAULT_PAGE_SIZE, Self::DEFAULT_MAX_PAGES)
+    }
+
+    /// Creates a new `ReadPile` from a unique reader, caching up to `max_pages` of `page_size`
+    /// bytes each.
+    pub fn with_cache(reader: Unique<'p, R>, page_size: usize, max_pages: usize) -> io::Result<Self> {
+        // Safe as no other reader has the lifetime 'p
+        unsafe { Self::with_cache_unchecked(Unique::into_inner(reader), page_size, max_pages) }
+    }
+
+    /// Creates a new `ReadPile`, caching up to `DEFAULT_MAX_PAGES` of `DEFAULT_PAGE_SIZE`.
+    ///
+    /// The pile's length is the reader's length when it's created; anything appended later is
+    /// out of range.
+    ///
+    /// # Safety
+    ///
+    /// As with `TryPile::new_unchecked()`, no other pile may have the same `'p` and `'v`.
+    pub unsafe fn new_unchecked(reader: R) -> io::Result<Self> {
+        Self::with_cache_unchecked(reader, Self::DEFAULT_PAGE_SIZE, Self::DEFAULT_MAX_PAGES)
+    }
+
+    /// Creates a new `ReadPile`, caching up to `max_pages` of `page_size` bytes each.
+    ///
+    /// # Safety
+    ///
+    /// See `new_unchecked()`.
+    pub unsafe fn with_cache_unchecked(mut reader: R, page_size: usize, max_pages: usize) -> io::Result<Self> {
+        assert!(page_size > 0);
+        assert!(max_pages > 0);
+
+        let len = reader.seek(SeekFrom::End(0))?;
+        let len = usize::try_from(len).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Ok(Self {
+            marker: PhantomData,
+            len,
+            inner: RefCell::new(Inner {
+                reader,
+                cache: PageCache {
+                    page_size,
+                    max_pages,
+                    tick: 0,
+                    pages: HashMap::new(),
+                    lru: BTreeMap::new(),
+                },
+                hits: 0,
+                misses: 0,
+            }),
+        })
+    }
+
+    /// Returns the length of the pile, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns an offset into this pile, or `None` if `offset` is past the end.
+    pub fn offset(&self, offset: usize) -> Option<Offset<'p, 'v>> {
+        if offset <= self.len {
+            Offset::new_any(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of page reads satisfied by the cache.
+    pub fn hits(&self) -> u64 {
+        self.inner.borrow().hits
+    }
+
+    /// Returns the number of page reads that had to read from the reader.
+    pub fn misses(&self) -> u64 {
+        self.inner.borrow().misses
+    }
+
+    /// Reads the bytes of a blob, without validating them.
+    pub fn read_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<Vec<u8>, Box<dyn std::error::Error>>
+        where T: BlobLen
+    {
+        let err = |kind| Error::<_, !>::new::<T>(offset, metadata, self.len, kind);
+
+        let blob_len = T::try_blob_len(metadata)
+                         .map_err(|e| err(ErrorKind::Layout(e)))?;
+
+        let start = offset.get();
+        match start.checked_add(blob_len) {
+            Some(end) if end <= self.len => {
+                let mut buf = vec![0; blob_len];
+                self.inner.borrow_mut().read_at(start, &mut buf)?;
+                Ok(buf)
+            },
+            _ => Err(err(ErrorKind::OutOfRange { blob_len }).into()),
+        }
+    }
+
+    fn load_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<T::Owned, Box<dyn std::error::Error>>
+        where T: Load<Offset<'p, 'v>>
+    {
+        let buf = self.read_blob::<T>(offset, metadata)?;
+
+        // The length was checked by read_blob()
+        let blob = unsafe { Blob::<T>::new_unchecked(&buf, metadata) };
+        let blob = T::validate_blob_ptr(BlobValidator::new_pileless(blob))
+                     .map_err(|e| Error::new::<T>(offset, metadata, self.len, ErrorKind::<!, _>::Validate(e)))?;
+
+        // Never read from, as values that embed a pile were rejected above.
+        let zone = Pile::default();
+        Ok(T::load_blob(BlobDecoder::new(blob, &zone)))
+    }
+}
+
+impl<R: Read + Seek> Inner<R> {
+    fn read_at(&mut self, mut pos: usize, mut dst: &mut [u8]) -> io::Result<()> {
+        let page_size = self.cache.page_size;
+        while !dst.is_empty() {
+            let (idx, start) = (pos / page_size, pos % page_size);
+
+            let page = match self.cache.get(idx) {
+                Some(page) => {
+                    self.hits += 1;
+                    page
+                },
+                None => {
+                    self.misses += 1;
+                    let page = read_page(&mut self.reader, idx, page_size)?;
+                    self.cache.insert(idx, page)
+                },
+            };
+
+            let src = page.get(start ..).unwrap_or(&[]);
+            let n = src.len().min(dst.len());
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "pile truncated"));
+            }
+            dst[.. n].copy_from_slice(&src[.. n]);
+
+            dst = &mut dst[n ..];
+            pos += n;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a page, which is short if it's the last one.
+fn read_page<R: Read + Seek>(reader: &mut R, idx: usize, page_size: usize) -> io::Result<Box<[u8]>> {
+    reader.seek(SeekFrom::Start((idx * page_size) as u64))?;
+
+    let mut page = Vec::with_capacity(page_size);
+    reader.take(page_size as u64).read_to_end(&mut page)?;
+    Ok(page.into())
+}
+
+impl PageCache {
+    fn get(&mut self, idx: usize) -> Option<&[u8]> {
+        let tick = self.next_tick();
+        let (last_used, page) = self.pages.get_mut(&idx)?;
+
+        self.lru.remove(last_used);
+        self.lru.insert(tick, idx);
+        *last_used = tick;
+        Some(page)
+    }
+
+    fn insert(&mut self, idx: usize, page: Box<[u8]>) -> &[u8] {
+        if self.pages.len() >= self.max_pages {
+            let (&oldest, &evicted) = self.lru.iter().next().expect("cache not empty");
+            self.lru.remove(&oldest);
+            self.pages.remove(&evicted);
+        }
+
+        let tick = self.next_tick();
+        self.lru.insert(tick, idx);
+        &self.pages.entry(idx).or_insert((tick, page)).1
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl<'p, 'v, R: Read + Seek> TryGet<Offset<'p, 'v>> for ReadPile<'p, 'v, R> {
+    type Error = Box<dyn std::error::Error>;
+
+    unsafe fn try_get_unchecked<'a, T: ?Sized>(&self, ptr: &'a Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<Ref<'a, T>, Self::Error>
+        where T: Load<Offset<'p, 'v>>
+    {
+        self.load_blob::<T>(*ptr, metadata).map(Ref::Owned)
+    }
+
+    unsafe fn try_take_unchecked<'a, T: ?Sized>(&self, ptr: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<T::Owned, Self::Error>
+        where T: Load<Offset<'p, 'v>>
+    {
+        self.load_blob::<T>(ptr, metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::Le;
+    use crate::heap::HeapPtr;
+
+    #[test]
+    fn read_pile() {
+        let (buf, offset) = crate::save::to_vec::<_, HeapPtr>(&[HeapPtr::alloc(Le::new(1u32)),
+                                                                HeapPtr::alloc(Le::new(2u32)),
+                                                                HeapPtr::alloc(Le::new(3u32))]);
+        assert_eq!(buf.len(), 12 + 24);
+
+        let pile = unsafe { ReadPile::with_cache_unchecked(Cursor::new(buf), 8, 2).unwrap() };
+        assert_eq!(pile.len(), 36);
+
+        let root: Own<[Own<Le<u32>, Offset>; 3], Offset> = unsafe { Own::new_unchecked(Fat::new(offset, ())) };
+        let root = root.try_get_in(&pile).unwrap();
+        assert!(matches!(root, Ref::Owned(_)));
+        let leaves: Vec<u32> = root.iter().map(|leaf| leaf.try_get_in(&pile).unwrap().get()).collect();
+        assert_eq!(leaves, &[1, 2, 3]);
+
+        // The root spans pages 1 to 4, and the leaves pages 0 and 1. Only two pages fit, so just
+        // the second leaf is a hit.
+        assert_eq!((pile.hits(), pile.misses()), (1, 4 + 2));
+
+        let leaf: Own<Le<u32>, Offset> = unsafe { Own::new_unchecked(Fat::new(Offset::new(4).unwrap(), ())) };
+        assert_eq!(leaf.try_get_in(&pile).unwrap().get(), 2);
+        assert_eq!((pile.hits(), pile.misses()), (2, 6));
+    }
+
+    #[test]
+    fn read_pile_errors() {
+        let pile = unsafe { ReadPile::new_unchecked(Cursor::new(vec![2u8])).unwrap() };
+
+        let own: Own<bool, Offset> = unsafe { Own::new_unchecked(Fat::new(Offset::new(0).unwrap(), ())) };
+        let err = own.try_get_in(&pile).unwrap_err();
+        assert!(err.to_string().starts_with("can't get `bool` blob at offset 0"));
+
+        let own: Own<u8, Offset> = unsafe { Own::new_unchecked(Fat::new(Offset::new(1).unwrap(), ())) };
+        let err = own.try_get_in(&pile).unwrap_err();
+        assert_eq!(err.source().unwrap().to_string(), "blob of length 1 runs past end of pile");
+        // There's no pile to decode as part of a value.
+        let own: Own<Pile, Offset> = unsafe { Own::new_unchecked(Fat::new(Offset::new(0).unwrap(), ())) };
+        let err = own.try_get_in(&pile).unwrap_err();
+        assert_eq!(err.source().unwrap().source().unwrap().to_string(), "value embeds a pile, which isn't available");
+    }
+
+    #[test]
+    fn read_pile_unique() -> io::Result<()> {
+        let buf = crate::save::to_vec::<_, HeapPtr>(&HeapPtr::alloc(Le::new(42u32))).0;
+        ReadPile::new(Cursor::new(buf), |pile| {
+            assert!(pile.offset(13).is_none());
+
+            let leaf: Own<Le<u32>, Offset> = unsafe { Own::new_unchecked(Fat::new(pile.offset(0).unwrap(), ())) };
+            assert_eq!(leaf.try_get_in(&pile).unwrap().get(), 42);
+        })
+    }
+}
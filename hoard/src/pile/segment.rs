@@ -0,0 +1,676 @@
+//! Piles split across a sequence of fixed-size segment files.
+//!
+//! Offset `n` is at byte `n % segment_len` of segment `n / segment_len`. Blobs never straddle
+//! segments: a blob that doesn't fit in what's left of the last segment seals it, and starts a new
+//! one. Sealed segments are never written to again, so they can be archived or checksummed on
+//! their own, and only the last segment needs remapping as it grows.
+//!
+//! Roots are committed by appending a record of the root's offset, and the length of the pile, to
+//! a separate commit log. Anything written after the last intact record, such as a blob torn by a
+//! crash, is discarded when the segments are next opened.
+
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use memmap::Mmap;
+use sha2::{Digest, Sha256};
+
+use super::*;
+use super::mapping::Mapping;
+
+/// The segment files of a pile, open for appending.
+#[derive(Debug)]
+pub struct Segments<'p> {
+    marker: PhantomData<fn(&'p ()) -> &'p ()>,
+    dir: PathBuf,
+    segment_len: usize,
+
+    /// Locked for as long as the segments are open.
+    meta: File,
+
+    commit_log: File,
+    commits: Vec<CommitRecord>,
+    discarded: usize,
+
+    sealed: Vec<Option<Mmap>>,
+    tip: File,
+    tip_len: usize,
+    tip_mapping: Mapping,
+}
+
+/// A pile over a set of segments.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentedPile<'p, 'v> {
+    marker: PhantomData<fn(&'p ()) -> &'p ()>,
+    segments: &'v Segments<'p>,
+}
+
+/// A record in the commit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CommitRecord {
+    root: Offset<'static, 'static>,
+
+    /// The length of the pile once the root was written.
+    len: usize,
+}
+
+impl CommitRecord {
+    const LEN: usize = 24;
+
+    /// Checksum of the offset and length, so a torn record is never mistaken for a commit.
+    fn check(bytes: &[u8]) -> [u8; 8] {
+        let mut check = [0; 8];
+        check.copy_from_slice(&Sha256::digest(bytes)[.. 8]);
+        check
+    }
+
+    fn encode(&self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        buf[0 .. 8].copy_from_slice(&(self.root.get() as u64).to_le_bytes());
+        buf[8 .. 16].copy_from_slice(&(self.len as u64).to_le_bytes());
+        let check = Self::check(&buf[.. 16]);
+        buf[16 ..].copy_from_slice(&check);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf[16 .. Self::LEN] != Self::check(&buf[.. 16]) {
+            return None;
+        }
+        let word = |range: std::ops::Range<usize>| {
+            u64::from_le_bytes(buf[range].try_into().unwrap()).try_into().ok()
+        };
+        let root = Offset::new(word(0 .. 8)?)?;
+        let len: usize = word(8 .. 16)?;
+        Some(Self { root, len }).filter(|commit| commit.root.get() <= commit.len)
+    }
+}
+
+/// Syncs a directory, so that files created or removed in it are durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+impl<'p> Segments<'p> {
+    const META_FILE: &'static str = "segment_len";
+    const COMMIT_FILE: &'static str = "commits";
+
+    /// Creates a new, empty, set of segments in `dir`, which must not already contain any.
+    pub fn create(dir: impl AsRef<Path>, segment_len: usize) -> io::Result<Self> {
+        assert!(segment_len > 0 && segment_len <= Offset::MAX);
+        let dir = dir.as_ref();
+
+        fs::create_dir_all(dir)?;
+        let mut meta = OpenOptions::new().read(true).write(true).create_new(true)
+                                         .open(dir.join(Self::META_FILE))?;
+        meta.try_lock_exclusive()?;
+        meta.write_all(&(segment_len as u64).to_le_bytes())?;
+        meta.sync_all()?;
+
+        let commit_log = OpenOptions::new().read(true).write(true).create_new(true)
+                                           .open(dir.join(Self::COMMIT_FILE))?;
+        let tip = Self::create_segment(dir, 0)?;
+        sync_dir(dir)?;
+
+        Self::new(dir, segment_len, meta, commit_log, vec![], vec![], tip)
+    }
+
+    /// Opens the segments in `dir`.
+    ///
+    /// Everything written after the last commit is discarded. Fails if another `Segments` has
+    /// them open.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+
+        let mut meta = OpenOptions::new().read(true).write(true).open(dir.join(Self::META_FILE))?;
+        meta.try_lock_exclusive()?;
+        let mut buf = [0; 8];
+        meta.read_exact(&mut buf)?;
+        let segment_len = u64::from_le_bytes(buf).try_into()
+                              .ok().filter(|&len| len > 0 && len <= Offset::MAX)
+                              .ok_or_else(|| invalid_data("invalid segment length"))?;
+
+        let (commit_log, commits) = Self::open_commit_log(dir)?;
+        let committed_len = commits.last().map(|commit| commit.len).unwrap_or(0);
+
+        // Segments before the one the last commit ended in were sealed before it was written.
+        let tip_idx = committed_len.saturating_sub(1) / segment_len;
+        let mut sealed = vec![];
+        for idx in 0 .. tip_idx {
+            let fd = File::open(Self::segment_path_in(dir, idx))?;
+            let len = fd.metadata()?.len();
+            if len > segment_len as u64 {
+                return Err(invalid_data(format!("segment {} is longer than the segment length", idx)));
+            }
+            sealed.push(if len > 0 { Some(unsafe { Mmap::map(&fd)? }) } else { None });
+        }
+
+        let tip = OpenOptions::new().read(true).write(true).open(Self::segment_path_in(dir, tip_idx))?;
+
+        let tip_len = committed_len - tip_idx * segment_len;
+        let file_len = tip.metadata()?.len() as usize;
+        if file_len < tip_len {
+            return Err(invalid_data(format!("segment {} is shorter than the last commit", tip_idx)));
+        }
+
+        // Anything past the last commit is torn, or was never committed.
+        let mut discarded = file_len - tip_len;
+        let mut removed = false;
+        let mut idx = tip_idx + 1;
+        loop {
+            let path = Self::segment_path_in(dir, idx);
+            match fs::metadata(&path) {
+                Ok(metadata) => {
+                    discarded += metadata.len() as usize;
+                    fs::remove_file(path)?;
+                    removed = true;
+                },
+                Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+                Err(err) => return Err(err),
+            }
+            idx += 1;
+        }
+
+        if file_len > tip_len {
+            tip.set_len(tip_len as u64)?;
+            tip.sync_all()?;
+        }
+        if removed {
+            sync_dir(dir)?;
+        }
+
+        let mut segments = Self::new(dir, segment_len, meta, commit_log, commits, sealed, tip)?;
+        segments.discarded = discarded;
+        Ok(segments)
+    }
+
+    /// Reads the commit log, truncating a torn record at its end.
+    fn open_commit_log(dir: &Path) -> io::Result<(File, Vec<CommitRecord>)> {
+        let mut fd = OpenOptions::new().read(true).write(true).open(dir.join(Self::COMMIT_FILE))?;
+        let mut buf = vec![];
+        fd.read_to_end(&mut buf)?;
+
+        let mut commits: Vec<CommitRecord> = vec![];
+        for record in buf.chunks_exact(CommitRecord::LEN) {
+            match CommitRecord::decode(record) {
+                Some(commit) if commits.last().map_or(true, |prev| prev.len <= commit.len) => {
+                    commits.push(commit);
+                },
+                _ => break,
+            }
+        }
+
+        let log_len = (commits.len() * CommitRecord::LEN) as u64;
+        if log_len < buf.len() as u64 {
+            fd.set_len(log_len)?;
+            fd.sync_all()?;
+        }
+        fd.seek(SeekFrom::Start(log_len))?;
+        Ok((fd, commits))
+    }
+
+    fn new(dir: &Path, segment_len: usize, meta: File, commit_log: File, commits: Vec<CommitRecord>,
+           sealed: Vec<Option<Mmap>>, mut tip: File) -> io::Result<Self>
+    {
+        let tip_mapping = Mapping::new(&tip)?;
+        let tip_len = tip_mapping.len();
+        if tip_len > segment_len {
+            return Err(invalid_data(format!("segment {} is longer than the segment length", sealed.len())));
+        }
+        tip.seek(SeekFrom::Start(tip_len as u64))?;
+
+        Ok(Self {
+            marker: PhantomData,
+            dir: dir.to_owned(),
+            segment_len,
+            meta,
+            commit_log,
+            commits,
+            discarded: 0,
+            sealed,
+            tip,
+            tip_len,
+            tip_mapping,
+        })
+    }
+
+    fn segment_path_in(dir: &Path, idx: usize) -> PathBuf {
+        dir.join(format!("{:08}.seg", idx))
+    }
+
+    fn create_segment(dir: &Path, idx: usize) -> io::Result<File> {
+        OpenOptions::new().read(true).write(true).create_new(true)
+                          .open(Self::segment_path_in(dir, idx))
+    }
+
+    /// Returns the path of segment `idx`.
+    pub fn segment_path(&self, idx: usize) -> PathBuf {
+        Self::segment_path_in(&self.dir, idx)
+    }
+
+    pub fn segment_len(&self) -> usize {
+        self.segment_len
+    }
+
+    /// Returns the number of segments, including the last one, which may be empty.
+    pub fn segment_count(&self) -> usize {
+        self.sealed.len() + 1
+    }
+
+    /// Returns the length of the pile: the offset the next blob would be written at, were there
+    /// room for it in the last segment.
+    pub fn len(&self) -> usize {
+        self.sealed.len() * self.segment_len + self.tip_len
+    }
+
+    /// Returns the bytes of segment `idx`.
+    pub fn segment(&self, idx: usize) -> Option<&[u8]> {
+        if idx < self.sealed.len() {
+            Some(self.sealed[idx].as_ref().map(|mmap| &mmap[..]).unwrap_or(&[]))
+        } else if idx == self.sealed.len() {
+            Some(&self.tip_mapping.as_bytes()[.. self.tip_len])
+        } else {
+            None
+        }
+    }
+
+    pub fn pile<'v>(&'v self) -> SegmentedPile<'p, 'v> {
+        SegmentedPile {
+            marker: PhantomData,
+            segments: self,
+        }
+    }
+
+    /// Returns the length of the pile as of the last commit.
+    pub fn committed_len(&self) -> usize {
+        self.commits.last().map(|commit| commit.len).unwrap_or(0)
+    }
+
+    /// Returns the number of uncommitted bytes discarded when the segments were opened, eg from a
+    /// blob whose write was torn by a crash.
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Returns the offsets of the committed roots, oldest first.
+    pub fn roots(&self) -> impl DoubleEndedIterator<Item = Offset<'static, 'static>> + '_ {
+        self.commits.iter().map(|commit| commit.root)
+    }
+
+    /// Returns the offset of the most recently committed root.
+    pub fn latest_root(&self) -> Option<Offset<'static, 'static>> {
+        self.roots().next_back()
+    }
+
+    /// Saves `root`, and everything dirty reachable from it, then commits it.
+    ///
+    /// Returns the offset of `root` once the commit is durable.
+    pub fn commit<'v, T: ?Sized>(&mut self, root: &T) -> io::Result<Offset<'static, 'static>>
+        where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        let root = self.save(root)?;
+
+        // The blobs must be durable before the record that points to them.
+        self.sync()?;
+
+        let commit = CommitRecord { root, len: self.len() };
+        self.commit_log.write_all(&commit.encode())?;
+        self.commit_log.sync_data()?;
+        self.commits.push(commit);
+        Ok(root)
+    }
+
+    /// Saves `root`, and everything dirty reachable from it, returning the offset of `root`.
+    ///
+    /// Clean pointers are assumed to be offsets into these segments, and are left as they are.
+    /// Nothing saved is kept past the next `open()` unless a later `commit()` covers it.
+    pub fn save<'v, T: ?Sized>(&mut self, root: &T) -> io::Result<Offset<'static, 'static>>
+        where T: Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        let saver = SegmentSaver {
+            marker: PhantomData,
+            segments: self,
+        };
+        let mut poll = root.init_save(&saver);
+        let r = poll.save_poll(saver)
+                    .and_then(|saver| saver.try_save_ptr(&poll))
+                    .map(|(_, offset)| offset);
+
+        // Even a failed save may have written some blobs.
+        self.tip_mapping.refresh()?;
+        r
+    }
+
+    /// Syncs the last segment to stable storage; earlier segments were synced when they filled.
+    pub fn sync(&self) -> io::Result<()> {
+        self.tip.sync_data()
+    }
+
+    /// Appends a blob, starting a new segment if it doesn't fit in the last one.
+    fn write_blob(&mut self, blob: &[u8]) -> io::Result<Offset<'static, 'static>> {
+        if blob.len() > self.segment_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} byte blob doesn't fit in a {} byte segment",
+                                              blob.len(), self.segment_len)));
+        }
+
+        if self.tip_len + blob.len() > self.segment_len {
+            self.seal()?;
+        }
+
+        let offset = Offset::new(self.len())
+                           .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "pile offset overflow"))?;
+        self.tip.write_all(blob)?;
+        self.tip_len += blob.len();
+        Ok(offset)
+    }
+
+    /// Seals the last segment, and starts a new one.
+    ///
+    /// The last segment is synced before its successor is created, so a crash never leaves a
+    /// sealed segment that isn't durable.
+    fn seal(&mut self) -> io::Result<()> {
+        self.tip.sync_all()?;
+        let sealed = if self.tip_len > 0 { Some(unsafe { Mmap::map(&self.tip)? }) } else { None };
+
+        let tip = Self::create_segment(&self.dir, self.sealed.len() + 1)?;
+        sync_dir(&self.dir)?;
+        let tip_mapping = Mapping::new(&tip)?;
+
+        self.sealed.push(sealed);
+        self.tip = tip;
+        self.tip_len = 0;
+        self.tip_mapping = tip_mapping;
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl<'p, 'v> SegmentedPile<'p, 'v> {
+    pub fn get_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<Blob<'v, T>, GetBlobError<T::LayoutError>>
+        where T: BlobLen
+    {
+        let err = |kind| Error::new::<T>(offset, metadata, self.segments.len(), kind);
+
+        let blob_len = T::try_blob_len(metadata)
+                         .map_err(|e| err(ErrorKind::Layout(e)))?;
+
+        let segment_len = self.segments.segment_len;
+        let (idx, start) = (offset.get() / segment_len, offset.get() % segment_len);
+
+        self.segments.segment(idx)
+            .and_then(|segment| {
+                start.checked_add(blob_len)
+                     .and_then(|end| segment.get(start .. end))
+            })
+            .map(|slice| unsafe { Blob::new_unchecked(slice, metadata) })
+            .ok_or_else(|| err(ErrorKind::OutOfRange { blob_len }))
+    }
+
+    pub fn get_valid_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<ValidBlob<'v, T>, GetValidBlobError<T::LayoutError, T::Error>>
+        where T: ValidateBlobPtr
+    {
+        self.validate_blob_with(offset, metadata, BlobValidator::from)
+    }
+
+    fn validate_blob_with<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata,
+                                     validator: impl FnOnce(Blob<'v, T>) -> BlobValidator<'v, T>)
+        -> Result<ValidBlob<'v, T>, GetValidBlobError<T::LayoutError, T::Error>>
+        where T: ValidateBlobPtr
+    {
+        let blob = self.get_blob::<T>(offset, metadata)
+                       .map_err(GetBlobError::into_valid)?;
+
+        T::validate_blob_ptr(validator(blob))
+          .map_err(|e| Error::new::<T>(offset, metadata, self.segments.len(), ErrorKind::Validate(e)))
+    }
+
+    /// Loads a blob for decoding.
+    ///
+    /// As with `ReadPile`, there's no single slice for values that embed their pile, so they're
+    /// rejected when validated.
+    fn load_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<ValidBlob<'v, T>, Box<dyn std::error::Error>>
+        where T: ValidateBlobPtr
+    {
+        Ok(self.validate_blob_with(offset, metadata, BlobValidator::new_pileless)?)
+    }
+}
+
+impl<'p, 'v> TryGet<Offset<'p, 'v>> for SegmentedPile<'p, 'v> {
+    type Error = Box<dyn std::error::Error>;
+
+    unsafe fn try_get_unchecked<'a, T: ?Sized>(&self, ptr: &'a Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<Ref<'a, T>, Self::Error>
+        where T: Load<Offset<'p, 'v>>
+    {
+        let blob = self.load_blob::<T>(*ptr, metadata)?;
+
+        // Never read from, as values that embed a pile were rejected above.
+        let zone = Pile::default();
+        Ok(T::deref_blob(BlobDecoder::new(blob, &zone)))
+    }
+
+    unsafe fn try_take_unchecked<'a, T: ?Sized>(&self, ptr: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<T::Owned, Self::Error>
+        where T: Load<Offset<'p, 'v>>
+    {
+        let blob = self.load_blob::<T>(ptr, metadata)?;
+
+        let zone = Pile::default();
+        Ok(T::load_blob(BlobDecoder::new(blob, &zone)))
+    }
+}
+
+impl<'p, 'v> TryGet<OffsetMut<'p, 'v>> for SegmentedPile<'p, 'v> {
+    type Error = Box<dyn std::error::Error>;
+
+    unsafe fn try_get_unchecked<'a, T: ?Sized>(&self, ptr: &'a OffsetMut<'p, 'v>, metadata: T::Metadata)
+        -> Result<Ref<'a, T>, Self::Error>
+        where T: Load<OffsetMut<'p, 'v>>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Ok(Ref::Ref(r)),
+            Err(offset) => {
+                let blob = self.load_blob::<T>(offset.cast(), metadata)?;
+
+                let zone = Pile::default();
+                Ok(T::deref_blob(BlobDecoder::new(blob, &zone)))
+            }
+        }
+    }
+
+    unsafe fn try_take_unchecked<'a, T: ?Sized>(&self, ptr: OffsetMut<'p, 'v>, metadata: T::Metadata)
+        -> Result<T::Owned, Self::Error>
+        where T: Load<OffsetMut<'p, 'v>>
+    {
+        match ptr.try_take_dirty_unchecked::<T>(metadata) {
+            Ok(owned) => Ok(owned),
+            Err(offset) => {
+                let blob = self.load_blob::<T>(offset.cast(), metadata)?;
+
+                let zone = Pile::default();
+                Ok(T::load_blob(BlobDecoder::new(blob, &zone)))
+            }
+        }
+    }
+}
+
+/// Saves into a set of segments.
+#[derive(Debug)]
+struct SegmentSaver<'a, 'p, 'v> {
+    marker: PhantomData<OffsetMut<'p, 'v>>,
+    segments: &'a mut Segments<'p>,
+}
+
+impl<'a, 'p, 'v> SavePtr for SegmentSaver<'a, 'p, 'v> {
+    type Source = OffsetMut<'p, 'v>;
+    type Target = Offset<'static, 'static>;
+    type Error = io::Error;
+
+    unsafe fn check_dirty<'b, T: ?Sized>(&self, ptr: &'b Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'b, T>>
+        where T: Load<Self::Source>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Err(Ref::Ref(r)),
+            Err(offset) => Ok(offset.to_static()),
+        }
+    }
+
+    fn try_save_ptr(self, saver: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        let blob = saver.save_blob(vec![]).into_ok();
+        let offset = self.segments.write_blob(&blob)?;
+        Ok((self, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::Le;
+
+    type Leaf<'p, 'v> = Own<Le<u64>, OffsetMut<'p, 'v>>;
+
+    #[test]
+    fn save_and_load() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut segments = Segments::create(dir.path(), 16)?;
+        assert_eq!(segments.segment_count(), 1);
+
+        let root: [Leaf; 2] = [OffsetMut::alloc(Le::new(1)), OffsetMut::alloc(Le::new(2))];
+        let offset = segments.commit(&root)?;
+
+        // The leaves fill the first segment, so the root starts the second.
+        assert_eq!(offset, 16);
+        assert_eq!(segments.segment_count(), 2);
+        assert_eq!(segments.len(), 32);
+        assert_eq!(segments.segment(0).unwrap(), &[1,0,0,0,0,0,0,0, 2,0,0,0,0,0,0,0]);
+
+        // A blob that doesn't fit leaves the rest of its segment empty.
+        let leaf: Leaf = OffsetMut::alloc(Le::new(3));
+        let offset2 = segments.commit(&leaf)?;
+        assert_eq!(offset2, 40);
+        assert_eq!(segments.len(), 48);
+        assert_eq!(segments.committed_len(), 48);
+
+        let load = |segments: &Segments| {
+            let pile = segments.pile();
+            let root: Own<[Own<Le<u64>, Offset>; 2], Offset> = unsafe { Own::new_unchecked(Fat::new(offset.cast(), ())) };
+            let root = root.try_get_in(&pile).unwrap();
+            let leaves: Vec<u64> = root.iter().map(|leaf| leaf.try_get_in(&pile).unwrap().get()).collect();
+            assert_eq!(leaves, &[1, 2]);
+
+            let leaf: Own<Own<Le<u64>, Offset>, Offset> = unsafe { Own::new_unchecked(Fat::new(offset2.cast(), ())) };
+            let leaf = leaf.try_get_in(&pile).unwrap();
+            assert_eq!(leaf.try_get_in(&pile).unwrap().get(), 3);
+        };
+        load(&segments);
+
+        drop(segments);
+        let segments = Segments::open(dir.path())?;
+        assert_eq!(segments.segment_count(), 3);
+        assert_eq!(segments.len(), 48);
+        assert_eq!(segments.discarded(), 0);
+        assert_eq!(segments.roots().collect::<Vec<_>>(), &[offset, offset2]);
+        load(&segments);
+        Ok(())
+    }
+
+    #[test]
+    fn discard_uncommitted() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut segments = Segments::create(dir.path(), 16)?;
+
+        let leaf: Leaf = OffsetMut::alloc(Le::new(1));
+        let root = segments.commit(&leaf)?;
+        assert_eq!(root, 8);
+
+        // Saved but never committed, filling the second segment and starting a third.
+        let leaves: [Leaf; 2] = [OffsetMut::alloc(Le::new(2)), OffsetMut::alloc(Le::new(3))];
+        segments.save(&leaves)?;
+        assert_eq!(segments.segment_count(), 3);
+        assert_eq!(segments.len(), 48);
+        drop(segments);
+
+        // A blob torn part way through, and a torn commit record.
+        let mut tip = OpenOptions::new().write(true).open(dir.path().join("00000002.seg"))?;
+        tip.seek(SeekFrom::End(0))?;
+        tip.write_all(&[4, 0, 0])?;
+        let mut log = OpenOptions::new().write(true).open(dir.path().join(Segments::COMMIT_FILE))?;
+        log.seek(SeekFrom::End(0))?;
+        log.write_all(&[32, 0, 0, 0])?;
+
+        let mut segments = Segments::open(dir.path())?;
+        assert_eq!(segments.discarded(), 16 + 16 + 3);
+        assert_eq!(segments.segment_count(), 1);
+        assert_eq!(segments.len(), 16);
+        assert_eq!(segments.latest_root(), Some(root));
+        assert!(!dir.path().join("00000001.seg").exists());
+        assert!(!dir.path().join("00000002.seg").exists());
+        assert_eq!(fs::metadata(dir.path().join(Segments::COMMIT_FILE))?.len(), 24);
+
+        // Writing resumes from the last commit.
+        let leaf: Leaf = OffsetMut::alloc(Le::new(5));
+        assert_eq!(segments.commit(&leaf)?, 24);
+        drop(segments);
+
+        let segments = Segments::open(dir.path())?;
+        assert_eq!(segments.discarded(), 0);
+        assert_eq!(segments.roots().collect::<Vec<_>>(), &[root, Offset::new(24).unwrap()]);
+        assert_eq!(segments.segment(1).unwrap(), &[5,0,0,0,0,0,0,0, 33,0,0,0,0,0,0,0]);
+        Ok(())
+    }
+
+    #[test]
+    fn try_get_offsetmut() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut segments = Segments::create(dir.path(), 64)?;
+
+        let leaf: Leaf = OffsetMut::alloc(Le::new(1));
+        let root = segments.commit(&leaf)?;
+
+        let pile = segments.pile();
+        let clean: Own<Leaf, OffsetMut> = unsafe { Own::new_unchecked(Fat::new(root.cast().into(), ())) };
+        let clean = clean.try_get_in(&pile).unwrap();
+        assert_eq!(clean.try_get_in(&pile).unwrap().get(), 1);
+
+        let dirty: Leaf = OffsetMut::alloc(Le::new(2));
+        assert_eq!(dirty.try_get_in(&pile).unwrap().get(), 2);
+        assert_eq!(dirty.try_take_in(&pile).unwrap().get(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut segments = Segments::create(dir.path(), 12)?;
+
+        // Locked while open.
+        assert!(Segments::open(dir.path()).is_err());
+        assert!(Segments::create(dir.path(), 12).is_err());
+
+        // Too big for any segment.
+        let root: [Leaf; 2] = [OffsetMut::alloc(Le::new(1)), OffsetMut::alloc(Le::new(2))];
+        let err = segments.save(&root).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Blobs never straddle segments.
+        let pile = segments.pile();
+        let err = pile.get_blob::<Le<u64>>(Offset::new(8).unwrap(), ()).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::OutOfRange { blob_len: 8 }));
+        assert!(pile.get_blob::<Le<u64>>(Offset::new(12).unwrap(), ()).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,593 @@
+//! Validation of everything reachable from a root, up front.
+//!
+//! Normally each blob is validated as it's loaded. That's fine for piles we wrote ourselves, but a
+//! pile from an untrusted source should be checked in full before any of it is used:
+//! `TryPile::validate_deep()` walks every pointer reachable from a root, validating each blob
+//! exactly once, and rejects blobs that are out of range or that overlap one another. The
+//! `Validated` token it returns provides a zone that skips re-validating those blobs.
+//!
+//! `TryPile::validate_deep_canonical()` does the same, but also rejects blobs that aren't
+//! canonically encoded, such as padding that isn't zeroed.
+//!
+//! The walk is driven by the same `Save` impls used to save values, so any type that can be saved
+//! to a pile can be validated deeply; nothing is actually written. Blobs still to be walked are kept
+//! on a worklist rather than recursed into, so a long chain of pointers can't overflow the stack.
+
+use std::any::{type_name, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error;
+use std::marker::PhantomData;
+use std::mem;
+
+use thiserror::Error;
+
+use super::*;
+use super::error::{Error, ErrorKind};
+
+/// An error found while validating a pile.
+#[derive(Debug, Error)]
+pub enum ValidateDeepError {
+    /// A blob couldn't be gotten, or failed validation.
+    #[error("invalid blob")]
+    Blob(#[source] Box<dyn error::Error + Send + Sync>),
+
+    /// Two blobs overlap.
+    #[error("`{type_name}` blob at {start}..{end} overlaps `{other_type_name}` blob at {other_start}..{other_end}")]
+    Overlap {
+        type_name: &'static str,
+        start: usize,
+        end: usize,
+        other_type_name: &'static str,
+        other_start: usize,
+        other_end: usize,
+    },
+}
+
+/// Returns the `TypeId` of `T` with its lifetimes erased.
+///
+/// `TypeId::of()` requires `T: 'static`, but types loaded from a pile borrow it. Lifetimes don't
+/// affect layout or validity, so types that only differ in their lifetimes can share a key.
+pub(crate) fn type_key<T: ?Sized>() -> TypeId {
+    trait NonStaticAny {
+        fn type_key(&self) -> TypeId where Self: 'static;
+    }
+
+    impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+        fn type_key(&self) -> TypeId where Self: 'static {
+            TypeId::of::<T>()
+        }
+    }
+
+    let phantom = PhantomData::<T>;
+    let phantom: &dyn NonStaticAny = &phantom;
+
+    // Lifetimes are erased before code generation, so the vtable is the same either way.
+    let phantom: &(dyn NonStaticAny + 'static) = unsafe { mem::transmute(phantom) };
+    phantom.type_key()
+}
+
+/// A validated blob.
+#[derive(Debug, Clone)]
+struct Visited {
+    end: usize,
+
+    /// Every type the blob has been validated as, with the name of the first for error messages.
+    types: Vec<TypeId>,
+    type_name: &'static str,
+}
+
+/// A validated blob whose pointers haven't been walked yet.
+#[derive(Debug)]
+struct Pending<'p, 'v> {
+    offset: Offset<'p, 'v>,
+
+    /// The blob's `T::Metadata`.
+    metadata: Box<dyn Any>,
+
+    /// `walk::<T>`, which doesn't name `T` in its type, so it can be kept whatever `T` borrows.
+    walk: fn(&Validator<'p, 'v>, Offset<'p, 'v>, &dyn Any) -> Result<(), ValidateDeepError>,
+}
+
+/// Walks a tree, validating each blob the first time it's reached as a given type.
+#[derive(Debug)]
+struct Validator<'p, 'v> {
+    pile: TryPile<'p, 'v>,
+
+    /// Validated blobs, by start offset. Zero-length blobs aren't recorded, as they can't overlap
+    /// anything.
+    blobs: RefCell<BTreeMap<usize, Visited>>,
+
+    /// Validated blobs still to be walked.
+    pending: RefCell<Vec<Pending<'p, 'v>>>,
+
+    /// Whether blobs must also be canonically encoded.
+    canonical: bool,
+
+    /// The first error found. `check_dirty()` can't fail, so errors are returned by the next call
+    /// to `try_save_ptr()` instead, or once the blob being walked is done.
+    error: RefCell<Option<ValidateDeepError>>,
+}
+
+impl<'p, 'v> Validator<'p, 'v> {
+    fn new(pile: TryPile<'p, 'v>, canonical: bool) -> Self {
+        Self {
+            pile,
+            blobs: RefCell::default(),
+            pending: RefCell::default(),
+            canonical,
+            error: RefCell::default(),
+        }
+    }
+
+    fn take_error(&self) -> Result<(), ValidateDeepError> {
+        self.error.borrow_mut().take().map_or(Ok(()), Err)
+    }
+
+    /// Validates the `T` at `offset`, and queues it to be walked.
+    fn push<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata) -> Result<(), ValidateDeepError>
+        where T: Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        if self.validate_blob::<T>(offset, metadata)? {
+            self.pending.borrow_mut().push(Pending {
+                offset,
+                metadata: Box::new(metadata),
+                walk: walk::<T>,
+            });
+        }
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<Pending<'p, 'v>> {
+        self.pending.borrow_mut().pop()
+    }
+
+    /// Walks queued blobs until there are none left.
+    fn run(&self) -> Result<(), ValidateDeepError> {
+        while let Some(pending) = self.pop() {
+            (pending.walk)(self, pending.offset, &*pending.metadata)?;
+        }
+        Ok(())
+    }
+
+    /// Validates the blob at `offset`, unless it's already been validated as a `T`.
+    ///
+    /// Returns `false` if it has, so that it isn't walked again. The same bytes can be validated as
+    /// more than one type; each is walked separately.
+    fn validate_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<bool, ValidateDeepError>
+        where T: ValidateBlobPtr
+    {
+        let blob = self.pile.get_blob::<T>(offset, metadata)
+                            .map_err(|err| ValidateDeepError::Blob(err.into()))?;
+
+        let start = offset.get();
+        let end = start + blob.as_bytes().len();
+        let key = type_key::<T>();
+
+        if start != end {
+            let mut blobs = self.blobs.borrow_mut();
+            match blobs.range_mut(.. end).next_back() {
+                Some((&other_start, other)) if other_start == start && other.end == end => {
+                    if other.types.contains(&key) {
+                        return Ok(false);
+                    }
+                    // Recorded before anything it points to is walked, so cycles terminate.
+                    other.types.push(key);
+                },
+                Some((&other_start, other)) if other.end > start => {
+                    return Err(ValidateDeepError::Overlap {
+                        type_name: type_name::<T>(),
+                        start, end,
+                        other_type_name: other.type_name,
+                        other_start,
+                        other_end: other.end,
+                    });
+                },
+                _ => {
+                    blobs.insert(start, Visited { end, types: vec![key], type_name: type_name::<T>() });
+                },
+            }
+        }
+
+        let blob = if self.canonical {
+            BlobValidator::new_canonical(blob)
+        } else {
+            blob.into()
+        };
+
+        T::validate_blob_ptr(blob)
+          .map(|_| true)
+          .map_err(|err| {
+              let err = Error::<T::LayoutError, _>::new::<T>(offset, metadata, self.pile.buf.len(),
+                                                             ErrorKind::Validate(err));
+              ValidateDeepError::Blob(err.into())
+          })
+    }
+}
+
+/// Walks the pointers of a queued `T`, queueing the blobs they point to in turn.
+fn walk<'p, 'v, T: ?Sized>(validator: &Validator<'p, 'v>, offset: Offset<'p, 'v>, metadata: &dyn Any)
+    -> Result<(), ValidateDeepError>
+    where T: Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+{
+    let metadata = *metadata.downcast_ref::<T::Metadata>().expect("metadata of the queued type");
+    let blob = validator.pile.get_blob::<T>(offset, metadata)
+                             .map_err(|err| ValidateDeepError::Blob(err.into()))?;
+
+    // Validated when it was queued.
+    let blob = unsafe { blob.assume_valid() };
+    let value = T::deref_blob(BlobDecoder::new(blob, validator.pile.coerce_valid()));
+
+    let mut poll = value.init_save(&validator);
+    poll.save_poll(validator)?;
+    validator.take_error()
+}
+
+impl<'a, 'p, 'v> SavePtr for &'a Validator<'p, 'v> {
+    type Source = OffsetMut<'p, 'v>;
+    type Target = Offset<'static, 'static>;
+    type Error = ValidateDeepError;
+
+    unsafe fn check_dirty<'b, T: ?Sized>(&self, ptr: &'b Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'b, T>>
+        where T: Load<Self::Source> + Save<Self::Source, Self::Target>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(value) => Err(Ref::Ref(value)),
+            Err(offset) if self.error.borrow().is_some() => Ok(offset.to_static()),
+            Err(offset) => {
+                // Queued rather than returned, so the encoder doesn't recurse into it.
+                if let Err(err) = self.push::<T>(offset, metadata) {
+                    *self.error.borrow_mut() = Some(err);
+                }
+                Ok(offset.to_static())
+            },
+        }
+    }
+
+    fn try_save_ptr(self, _: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        // Nothing is written, so there's no real pointer to return.
+        self.take_error()?;
+        Ok((self, Offset::dangling()))
+    }
+}
+
+/// Proof that everything reachable from a root has been validated.
+#[derive(Debug)]
+pub struct Validated<'p, 'v, T> {
+    marker: PhantomData<fn() -> T>,
+    root: OffsetMut<'p, 'v>,
+    pile: TryPile<'p, 'v>,
+    blobs: BTreeMap<usize, Visited>,
+}
+
+impl<'p, 'v> TryPile<'p, 'v> {
+    /// Validates the `T` at `root`, and everything reachable from it.
+    pub fn validate_deep<T>(&self, root: Offset<'p, 'v>) -> Result<Validated<'p, 'v, T>, ValidateDeepError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.validate_deep_with(root, false)
+    }
+
+    /// Like `validate_deep()`, but also rejects blobs that aren't canonically encoded.
+    pub fn validate_deep_canonical<T>(&self, root: Offset<'p, 'v>) -> Result<Validated<'p, 'v, T>, ValidateDeepError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        self.validate_deep_with(root, true)
+    }
+
+    fn validate_deep_with<T>(&self, root: Offset<'p, 'v>, canonical: bool)
+        -> Result<Validated<'p, 'v, T>, ValidateDeepError>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>> + Save<OffsetMut<'p, 'v>, Offset<'static, 'static>>
+    {
+        let validator = Validator::new(*self, canonical);
+        validator.push::<T>(root, T::make_sized_metadata())?;
+        validator.run()?;
+
+        Ok(Validated {
+            marker: PhantomData,
+            root: root.into(),
+            pile: validator.pile,
+            blobs: validator.blobs.into_inner(),
+        })
+    }
+}
+
+impl<'p, 'v, T> Validated<'p, 'v, T> {
+    /// The offset of the root.
+    pub fn root_offset(&self) -> Offset<'p, 'v> {
+        self.root.get_offset().expect("root is always an offset")
+    }
+
+    /// The number of distinct, non-empty, blobs that were validated.
+    pub fn blob_count(&self) -> usize {
+        self.blobs.len()
+    }
+
+    /// Returns a zone that trusts the validated blobs.
+    pub fn zone(&self) -> ValidatedPile<'_, 'p, 'v> {
+        ValidatedPile {
+            pile: self.pile,
+            blobs: &self.blobs,
+        }
+    }
+
+    /// Gets the root.
+    pub fn get(&self) -> Ref<'_, T>
+        where T: ValidateBlob + Load<OffsetMut<'p, 'v>>
+    {
+        unsafe { self.zone().try_get_unchecked::<T>(&self.root, T::make_sized_metadata()) }
+            .ok().expect("root was validated")
+    }
+}
+
+/// A pile zone that skips validation of blobs already validated by `TryPile::validate_deep()`.
+///
+/// Anything else is validated as usual, so it's safe to use with any pointer: blobs that are
+/// invalid, or out of range, are returned as errors.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidatedPile<'a, 'p, 'v> {
+    pile: TryPile<'p, 'v>,
+    blobs: &'a BTreeMap<usize, Visited>,
+}
+
+impl<'a, 'p, 'v> ValidatedPile<'a, 'p, 'v> {
+    pub fn get_valid_blob<T: ?Sized>(&self, offset: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<ValidBlob<'v, T>, GetValidBlobError<T::LayoutError, T::Error>>
+        where T: ValidateBlobPtr
+    {
+        let blob = self.pile.get_blob::<T>(offset, metadata)
+                            .map_err(GetBlobError::into_valid)?;
+
+        let start = offset.get();
+        match self.blobs.get(&start) {
+            Some(visited) if visited.end == start + blob.as_bytes().len()
+                          && visited.types.contains(&type_key::<T>())
+                => Ok(unsafe { blob.assume_valid() }),
+            _ => self.pile.get_valid_blob::<T>(offset, metadata),
+        }
+    }
+}
+
+impl<'a, 'p, 'v> TryGet<Offset<'p, 'v>> for ValidatedPile<'a, 'p, 'v> {
+    type Error = Box<dyn error::Error>;
+
+    unsafe fn try_get_unchecked<'b, T: ?Sized>(&self, ptr: &'b Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<Ref<'b, T>, Self::Error>
+        where T: Load<Offset<'p, 'v>>
+    {
+        let blob = self.get_valid_blob::<T>(*ptr, metadata)?;
+        Ok(T::deref_blob(BlobDecoder::new(blob, self.pile.coerce_valid())))
+    }
+
+    unsafe fn try_take_unchecked<'b, T: ?Sized>(&self, ptr: Offset<'p, 'v>, metadata: T::Metadata)
+        -> Result<T::Owned, Self::Error>
+        where T: Load<Offset<'p, 'v>>
+    {
+        let blob = self.get_valid_blob::<T>(ptr, metadata)?;
+        Ok(T::load_blob(BlobDecoder::new(blob, self.pile.coerce_valid())))
+    }
+}
+
+impl<'a, 'p, 'v> TryGet<OffsetMut<'p, 'v>> for ValidatedPile<'a, 'p, 'v> {
+    type Error = Box<dyn error::Error>;
+
+    unsafe fn try_get_unchecked<'b, T: ?Sized>(&self, ptr: &'b OffsetMut<'p, 'v>, metadata: T::Metadata)
+        -> Result<Ref<'b, T>, Self::Error>
+        where T: Load<OffsetMut<'p, 'v>>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Ok(Ref::Ref(r)),
+            Err(offset) => {
+                let blob = self.get_valid_blob::<T>(offset, metadata)?;
+                Ok(T::deref_blob(BlobDecoder::new(blob, self.pile.coerce_valid())))
+            },
+        }
+    }
+
+    unsafe fn try_take_unchecked<'b, T: ?Sized>(&self, ptr: OffsetMut<'p, 'v>, metadata: T::Metadata)
+        -> Result<T::Owned, Self::Error>
+        where T: Load<OffsetMut<'p, 'v>>
+    {
+        match ptr.try_take_dirty_unchecked::<T>(metadata) {
+            Ok(owned) => Ok(owned),
+            Err(offset) => {
+                let blob = self.get_valid_blob::<T>(offset, metadata)?;
+                Ok(T::load_blob(BlobDecoder::new(blob, self.pile.coerce_valid())))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Le;
+
+    fn raw_offset(offset: u64) -> [u8; 8] {
+        (offset << 1 | 1).to_le_bytes()
+    }
+
+    #[test]
+    fn validate_deep() {
+        type Root<'p, 'v> = [Own<Option<Own<u8, OffsetMut<'p, 'v>>>, OffsetMut<'p, 'v>>; 2];
+
+        let (buf, offset) = TryPile::default().save_to_vec(&[OffsetMut::alloc(Some(OffsetMut::alloc(1u8))),
+                                                             OffsetMut::alloc(None)]);
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let validated = pile.validate_deep::<Root>(offset.cast()).unwrap();
+
+        // The root, two options, and one u8
+        assert_eq!(validated.blob_count(), 4);
+
+        let zone = validated.zone();
+        let root = validated.get();
+        let leaf = root[0].try_get_in(&zone).unwrap();
+        assert_eq!(*leaf.as_ref().unwrap().try_get_in(&zone).unwrap(), 1);
+        assert!(root[1].try_get_in(&zone).unwrap().is_none());
+    }
+
+    #[test]
+    fn validate_deep_shared() {
+        // Two pointers to the same u8, which is only validated once.
+        let mut buf = vec![42, 0, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&raw_offset(0));
+        buf.extend_from_slice(&raw_offset(0));
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+
+        let validated = pile.validate_deep::<[Own<u8, Offset>; 2]>(Offset::new(8).unwrap()).unwrap();
+        assert_eq!(validated.blob_count(), 2);
+        assert_eq!(*validated.get()[1].try_get_in(&validated.zone()).unwrap(), 42);
+    }
+
+    /// A linked list, for chains of pointers longer than the stack is deep.
+    #[derive(Debug)]
+    struct Link<'p, 'v>(Option<Own<Link<'p, 'v>, OffsetMut<'p, 'v>>>);
+
+    type LinkField<'p, 'v> = Option<Own<Link<'p, 'v>, OffsetMut<'p, 'v>>>;
+    type LinkFieldPoll<'p, 'v> = <LinkField<'p, 'v> as Encode<OffsetMut<'p, 'v>, Offset<'static, 'static>>>::EncodePoll;
+
+    impl ValidateBlob for Link<'_, '_> {
+        const BLOB_LEN: usize = <LinkField as ValidateBlob>::BLOB_LEN;
+        type Error = <LinkField<'static, 'static> as ValidateBlob>::Error;
+
+        fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
+            blob.field::<LinkField, _>(|err| err)?;
+            unsafe { blob.finish() }
+        }
+    }
+
+    impl<'p, 'v> Decode<OffsetMut<'p, 'v>> for Link<'p, 'v> {
+        fn decode_blob(mut blob: BlobDecoder<OffsetMut<'p, 'v>, Self>) -> Self {
+            Link(unsafe { blob.field_unchecked() })
+        }
+    }
+
+    struct LinkPoll<'p, 'v>(Box<LinkFieldPoll<'p, 'v>>);
+
+    impl<'p, 'v> Encode<OffsetMut<'p, 'v>, Offset<'static, 'static>> for Link<'p, 'v> {
+        type EncodePoll = LinkPoll<'p, 'v>;
+
+        fn init_encode(&self, dst: &impl SavePtr<Source=OffsetMut<'p, 'v>, Target=Offset<'static, 'static>>) -> Self::EncodePoll {
+            LinkPoll(Box::new(self.0.init_encode(dst)))
+        }
+    }
+
+    impl<'p, 'v> SavePoll<OffsetMut<'p, 'v>, Offset<'static, 'static>> for LinkPoll<'p, 'v> {
+        fn save_poll<D>(&mut self, dst: D) -> Result<D, D::Error>
+            where D: SavePtr<Source=OffsetMut<'p, 'v>, Target=Offset<'static, 'static>>
+        {
+            self.0.save_poll(dst)
+        }
+    }
+
+    impl EncodeBlob for LinkPoll<'_, '_> {
+        const BLOB_LEN: usize = <Link as ValidateBlob>::BLOB_LEN;
+
+        fn encode_blob<W: WriteBlob>(&self, dst: W) -> Result<W::Done, W::Error> {
+            dst.write(&*self.0)?.done()
+        }
+    }
+
+    #[test]
+    fn validate_deep_long_chain() {
+        const LEN: usize = 100_000;
+
+        // Each link points to the one before it.
+        let mut buf = vec![0; 9];
+        for i in 1 .. LEN {
+            buf.push(1);
+            buf.extend_from_slice(&raw_offset((i as u64 - 1) * 9));
+        }
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+
+        let validated = pile.validate_deep::<Link>(Offset::new((LEN - 1) * 9).unwrap()).unwrap();
+        assert_eq!(validated.blob_count(), LEN);
+
+        // A bad link at the far end is still found.
+        buf[0] = 2;
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        assert!(pile.validate_deep::<Link>(Offset::new((LEN - 1) * 9).unwrap()).is_err());
+    }
+
+    #[test]
+    fn validated_pile_errors() {
+        let mut buf = vec![42, 2, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&raw_offset(0));
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let validated = pile.validate_deep::<Own<u8, Offset>>(Offset::new(8).unwrap()).unwrap();
+        let zone = validated.zone();
+
+        // Pointers to blobs that weren't validated are validated on the way.
+        let bad: Own<bool, Offset> = unsafe { Own::new_unchecked(Fat::new(Offset::new(1).unwrap(), ())) };
+        let err = bad.try_get_in(&zone).unwrap_err();
+        assert_eq!(err.to_string(), "can't get `bool` blob at offset 1 (metadata (), pile length 16)");
+
+        let out_of_range: Own<u8, Offset> = unsafe { Own::new_unchecked(Fat::new(Offset::new(16).unwrap(), ())) };
+        assert!(out_of_range.try_get_in(&zone).is_err());
+    }
+
+    #[test]
+    fn validate_deep_errors() {
+        // Overlapping blobs
+        let mut buf = vec![1, 2, 3, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&raw_offset(0));
+        buf.extend_from_slice(&raw_offset(1));
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        match pile.validate_deep::<[Own<Le<u16>, Offset>; 2]>(Offset::new(8).unwrap()) {
+            Err(ValidateDeepError::Overlap { start: 1, end: 3, other_start: 0, other_end: 2, .. }) => {},
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+
+        // The same bytes can be used as two different types, but not a blob within another.
+        let buf = raw_offset(0);
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        match pile.validate_deep::<Own<Own<u8, Offset>, Offset>>(Offset::new(0).unwrap()) {
+            Err(ValidateDeepError::Overlap { start: 0, end: 1, other_start: 0, other_end: 8, .. }) => {},
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+
+        // Out of range
+        let buf = raw_offset(100);
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        match pile.validate_deep::<Own<u8, Offset>>(Offset::new(0).unwrap()) {
+            Err(ValidateDeepError::Blob(err)) => {
+                assert_eq!(err.to_string(), "can't get `u8` blob at offset 100 (metadata (), pile length 8)");
+            },
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+
+        // An invalid leaf
+        let mut buf = vec![2, 0, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&raw_offset(0));
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        assert!(pile.validate_deep::<Own<bool, Offset>>(Offset::new(8).unwrap()).is_err());
+    }
+
+    #[test]
+    fn validate_deep_canonical() {
+        // A `None` behind a pointer, with a non-zero padding byte.
+        let mut buf = vec![0, 0, 0, 7, 0, 0, 0, 0];
+        buf.extend_from_slice(&raw_offset(0));
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let root = Offset::new(8).unwrap();
+
+        assert!(pile.validate_deep::<Own<Option<Le<u32>>, Offset>>(root).is_ok());
+        match pile.validate_deep_canonical::<Own<Option<Le<u32>>, Offset>>(root) {
+            Err(ValidateDeepError::Blob(err)) => {
+                let mut err: &dyn error::Error = &*err;
+                while let Some(source) = err.source() {
+                    err = source;
+                }
+                assert_eq!(err.to_string(), "non-zero padding at bytes 3..4");
+            },
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+
+        buf[3] = 0;
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let validated = pile.validate_deep_canonical::<Own<Option<Le<u32>>, Offset>>(root).unwrap();
+        assert!(validated.get().try_get_in(&validated.zone()).unwrap().is_none());
+    }
+}
@@ -1,9 +1,11 @@
 use std::num;
 use std::convert::TryFrom;
 
+use thiserror::Error;
+
 use crate::save::*;
 use crate::load::{Decode, BlobDecoder};
-use crate::blob::{Blob, ValidateBlob};
+use crate::blob::{Blob, BlobError, BlobValidator, ValidateBlob};
 
 use leint::Le;
 
@@ -12,15 +14,40 @@ pub trait Primitive : Decode<!> + Encode<!, !> {
         vec![].write_primitive(self).into_ok()
     }
 
-    fn try_decode_blob_bytes(src: &[u8]) -> Result<Self, Self::Error>
+    fn try_decode_blob_bytes(src: &[u8]) -> Result<Self, DecodeBytesError<Self::Error>>
+        where Self: Sized
+    {
+        let blob = Blob::<Self>::try_from(src)
+                        .map_err(|_| BlobError::Length { expected: Self::BLOB_LEN, actual: src.len() })?;
+        let valid_blob = Self::validate_blob(blob.into())
+                              .map_err(DecodeBytesError::Validate)?;
+        Ok(Self::decode_blob(BlobDecoder::new(valid_blob, &())))
+    }
+
+    /// Like `try_decode_blob_bytes()`, but also rejects bytes that aren't canonically encoded.
+    ///
+    /// Anything this accepts encodes back to exactly the same bytes.
+    fn try_decode_canonical_blob_bytes(src: &[u8]) -> Result<Self, DecodeBytesError<Self::Error>>
         where Self: Sized
     {
-        let blob = Blob::<Self>::try_from(src).unwrap();
-        let valid_blob = Self::validate_blob(blob.into())?;
+        let blob = Blob::<Self>::try_from(src)
+                        .map_err(|_| BlobError::Length { expected: Self::BLOB_LEN, actual: src.len() })?;
+        let valid_blob = Self::validate_blob(BlobValidator::new_canonical(blob))
+                              .map_err(DecodeBytesError::Validate)?;
         Ok(Self::decode_blob(BlobDecoder::new(valid_blob, &())))
     }
 }
 
+/// An error decoding a primitive from bytes.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecodeBytesError<E: std::error::Error> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error(transparent)]
+    Validate(E),
+}
+
 impl<T: Primitive, const N: usize> Primitive for [T; N] {}
 
 impl<T: Primitive> Primitive for Option<T> {}
@@ -43,14 +70,14 @@ impl_primitive! {
 
 pub fn test_option_decode(src: &[u8;2])
     -> Result<Option<bool>,
-              crate::blob::impls::option::ValidateBlobOptionError<crate::blob::impls::scalars::ValidateBoolError>>
+              DecodeBytesError<crate::blob::impls::option::ValidateBlobOptionError<crate::blob::impls::scalars::ValidateBoolError>>>
 {
     Primitive::try_decode_blob_bytes(src)
 }
 
 pub fn test_array_decode(src: &[u8;4])
     -> Result<[bool; 4],
-              crate::blob::impls::array::ValidateArrayError<crate::blob::impls::scalars::ValidateBoolError, 4>>
+              DecodeBytesError<crate::blob::impls::array::ValidateArrayError<crate::blob::impls::scalars::ValidateBoolError, 4>>>
 {
     Primitive::try_decode_blob_bytes(src)
 }
@@ -66,6 +93,9 @@ pub fn test_array_decode2(src: &[u8;2])
 mod tests {
     use super::*;
 
+    use crate::blob::impls::array::ValidateArrayError;
+    use crate::blob::impls::option::ValidateBlobOptionError;
+
     #[test]
     fn test_option() {
         assert_eq!(Some(42u8).encode_blob_bytes(),
@@ -86,4 +116,93 @@ mod tests {
         assert_eq!(<u32 as Primitive>::try_decode_blob_bytes(&[0x78, 0x56, 0x34, 0x12]),
                    Ok(0x12345678));
     }
+
+    #[test]
+    fn test_wrong_length() {
+        assert_eq!(<u32 as Primitive>::try_decode_blob_bytes(&[1, 2, 3]),
+                   Err(DecodeBytesError::Blob(BlobError::Length { expected: 4, actual: 3 })));
+        assert!(<Option<bool> as Primitive>::try_decode_blob_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(<Option<u8> as Primitive>::try_decode_blob_bytes(&[0, 5]), Ok(None));
+        assert_eq!(<Option<u8> as Primitive>::try_decode_canonical_blob_bytes(&[0, 5]),
+                   Err(DecodeBytesError::Validate(ValidateBlobOptionError::Blob(BlobError::Padding { start: 1, end: 2 }))));
+
+        // The range is relative to the outermost blob.
+        let mut buf = [1, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        buf[7] = 1;
+        match <[Option<Le<u32>>; 2] as Primitive>::try_decode_canonical_blob_bytes(&buf) {
+            Err(DecodeBytesError::Validate(ValidateArrayError::Element {
+                idx: 1,
+                err: ValidateBlobOptionError::Blob(BlobError::Padding { start: 7, end: 8 }),
+            })) => {},
+            r => panic!("{:?}", r),
+        }
+        buf[7] = 0;
+        assert!(<[Option<Le<u32>>; 2] as Primitive>::try_decode_canonical_blob_bytes(&buf).is_ok());
+    }
+
+    /// Feeds random bytes, of random lengths, to every built-in primitive.
+    ///
+    /// Decoding may fail, but must never panic; whatever does decode must re-encode to bytes that
+    /// decode to the same value. Those are only guaranteed to be the original bytes if they were
+    /// decoded in canonical mode.
+    #[test]
+    fn fuzz_try_decode_blob_bytes() {
+        // xorshift64*, so the test is deterministic and needs no dependencies.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        };
+
+        fn check<T: Primitive + PartialEq + std::fmt::Debug>(buf: &[u8]) {
+            if let Ok(value) = T::try_decode_blob_bytes(buf) {
+                assert_eq!(buf.len(), T::BLOB_LEN);
+                let encoded = value.encode_blob_bytes();
+                assert_eq!(T::try_decode_blob_bytes(&encoded).as_ref().ok(), Some(&value));
+                assert_eq!(T::try_decode_canonical_blob_bytes(&encoded).ok(), Some(value));
+            }
+
+            // Canonical encodings round-trip exactly.
+            if let Ok(value) = T::try_decode_canonical_blob_bytes(buf) {
+                assert_eq!(value.encode_blob_bytes(), buf);
+            }
+        }
+
+        macro_rules! check_all {
+            ($buf:expr; $($t:ty,)+) => {$(
+                check::<$t>($buf);
+                check::<Option<$t>>($buf);
+                check::<[$t; 3]>($buf);
+                check::<Option<[Option<$t>; 2]>>($buf);
+            )+}
+        }
+
+        for _ in 0 .. 2000 {
+            let r = next();
+
+            // Mostly zeros and ones, so that bools, options and nonzeros often decode.
+            let len = (r % 40) as usize;
+            let buf: Vec<u8> = (0 .. len).map(|_| match next() % 4 {
+                                                0 => 0,
+                                                1 => 1,
+                                                _ => next() as u8,
+                                            }).collect();
+
+            check_all! { &buf;
+                (), bool,
+                u8, Le<u16>, Le<u32>, Le<u64>, Le<u128>,
+                i8, Le<i16>, Le<i32>, Le<i64>, Le<i128>,
+                u16, u32, u64, u128,
+                i16, i32, i64, i128,
+                num::NonZeroU8, Le<num::NonZeroU16>, Le<num::NonZeroU32>, Le<num::NonZeroU64>, Le<num::NonZeroU128>,
+                num::NonZeroI8, Le<num::NonZeroI16>, Le<num::NonZeroI32>, Le<num::NonZeroI64>, Le<num::NonZeroI128>,
+            }
+        }
+    }
 }
@@ -63,10 +63,15 @@ where P: Eq
 {}
 
 #[derive(Debug, Error)]
-#[error("fixme")]
-pub enum ValidateFatBlobError<P: fmt::Debug, M: fmt::Debug> {
-    Ptr(P),
-    Metadata(M),
+pub enum ValidateFatBlobError<P: std::error::Error + 'static, M: std::error::Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid pointer")]
+    Ptr(#[source] P),
+
+    #[error("invalid pointer metadata")]
+    Metadata(#[source] M),
 }
 
 impl<T: ?Sized, P, M> ValidateBlob for Fat<T, P, M>
@@ -78,9 +83,9 @@ where P: ValidateBlob,
     const BLOB_LEN: usize = P::BLOB_LEN + M::BLOB_LEN;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        blob.field::<P>().map_err(ValidateFatBlobError::Ptr)?;
-        blob.field::<M>().map_err(ValidateFatBlobError::Metadata)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<P, _>(ValidateFatBlobError::Ptr)?;
+        blob.field::<M, _>(ValidateFatBlobError::Metadata)?;
+        unsafe { blob.finish() }
     }
 }
 
@@ -115,10 +115,15 @@ impl<T: ?Sized + Pointee, P: Ptr, M> Own<T, P, M> {
 }
 
 #[derive(Debug, Error)]
-#[error("FIXME")]
-pub enum ValidateOwnBlobError<P: fmt::Debug, M: fmt::Debug> {
-    Ptr(P),
-    Metadata(M),
+pub enum ValidateOwnBlobError<P: std::error::Error + 'static, M: std::error::Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid pointer")]
+    Ptr(#[source] P),
+
+    #[error("invalid pointer metadata")]
+    Metadata(#[source] M),
 }
 
 impl<T: ?Sized + Pointee, P: Ptr, M> ValidateBlob for Own<T, P, M>
@@ -130,9 +135,9 @@ where P: ValidateBlob,
     const BLOB_LEN: usize = P::BLOB_LEN + M::BLOB_LEN;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        blob.field::<P>().map_err(ValidateOwnBlobError::Ptr)?;
-        blob.field::<M>().map_err(ValidateOwnBlobError::Metadata)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<P, _>(ValidateOwnBlobError::Ptr)?;
+        blob.field::<M, _>(ValidateOwnBlobError::Metadata)?;
+        unsafe { blob.finish() }
     }
 }
 
@@ -173,9 +178,9 @@ enum State<T, R> {
     Done(R),
 }
 
-impl<Q, R, T: ?Sized + Pointee, P: Ptr> Encode<Q, R> for Own<T, P>
+impl<Q: Ptr, R, T: ?Sized + Pointee, P: Ptr> Encode<Q, R> for Own<T, P>
 where R: Primitive,
-      T: Save<Q, R>,
+      T: Save<Q, R> + Load<Q>,
       T::Metadata: Primitive,
       P: AsPtr<Q>,
 {
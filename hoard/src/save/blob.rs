@@ -1,7 +1,10 @@
 use std::error;
 use std::mem;
 
+use owned::IntoOwned;
+
 use crate::save::*;
+use crate::refs::Ref;
 use crate::pointee::Pointee;
 use crate::primitive::Primitive;
 use crate::blob::BlobLen;
@@ -40,7 +43,9 @@ impl SavePtr for DummySavePtr {
     type Target = !;
     type Error = !;
 
-    unsafe fn check_dirty<'a, T: ?Sized + Pointee>(&self, ptr: &'a !, _: T::Metadata) -> Result<!, &'a T> {
+    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a !, _: T::Metadata) -> Result<!, Ref<'a, T>>
+        where T: Pointee + IntoOwned
+    {
         match *ptr {}
     }
 
@@ -0,0 +1,167 @@
+//! Deduplication of identical blobs.
+
+use std::any::type_name;
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use super::*;
+
+/// Remembers where blobs were saved, so that identical blobs are only saved once.
+///
+/// Blobs are identified by the SHA256 digest of their bytes, along with the type of the encoder
+/// that produced them; two values only share a blob if they're the same type and encode to the
+/// same bytes. Only the digest is kept, not the bytes themselves.
+#[derive(Debug, Clone)]
+pub struct Dedup<P> {
+    blobs: HashMap<BlobKey, P>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BlobKey {
+    type_name: &'static str,
+    digest: [u8; 32],
+}
+
+impl<P> Default for Dedup<P> {
+    fn default() -> Self {
+        Self { blobs: HashMap::new() }
+    }
+}
+
+impl<P: Clone> Dedup<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of distinct blobs saved.
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+
+    /// Forgets every blob saved so far.
+    pub fn clear(&mut self) {
+        self.blobs.clear()
+    }
+
+    /// Saves a blob, unless an identical blob has already been saved.
+    ///
+    /// The blob is encoded, and if it's new, `save` is called with its bytes to actually save it.
+    /// Either way, the pointer to the saved blob is returned. If `save` fails nothing is recorded.
+    pub fn save_blob<E>(
+        &mut self,
+        saver: &impl SaveBlob,
+        save: impl FnOnce(&[u8]) -> Result<P, E>
+    ) -> Result<P, E> {
+        let bytes = saver.save_blob(vec![]).into_ok();
+        let key = BlobKey::new(type_name_of(saver), &bytes);
+
+        if let Some(ptr) = self.blobs.get(&key) {
+            Ok(ptr.clone())
+        } else {
+            let ptr = save(&bytes)?;
+            self.blobs.insert(key, ptr.clone());
+            Ok(ptr)
+        }
+    }
+
+    /// Saves a blob like `save_blob`, but records it in `pending` rather than in `self`.
+    ///
+    /// Blobs already in either table aren't saved again. Once the blobs in `pending` are known to
+    /// have been kept, `extend` merges them in.
+    pub fn save_blob_pending<E>(
+        &self,
+        pending: &mut Self,
+        saver: &impl SaveBlob,
+        save: impl FnOnce(&[u8]) -> Result<P, E>
+    ) -> Result<P, E> {
+        let bytes = saver.save_blob(vec![]).into_ok();
+        let key = BlobKey::new(type_name_of(saver), &bytes);
+
+        if let Some(ptr) = self.blobs.get(&key).or_else(|| pending.blobs.get(&key)) {
+            Ok(ptr.clone())
+        } else {
+            let ptr = save(&bytes)?;
+            pending.blobs.insert(key, ptr.clone());
+            Ok(ptr)
+        }
+    }
+
+    /// Remembers every blob in `other` as well.
+    pub fn extend(&mut self, other: Self) {
+        self.blobs.extend(other.blobs)
+    }
+}
+
+fn type_name_of<T: ?Sized>(_: &T) -> &'static str {
+    type_name::<T>()
+}
+
+impl BlobKey {
+    fn new(type_name: &'static str, bytes: &[u8]) -> Self {
+        let mut digest = [0; 32];
+        digest.copy_from_slice(&Sha256::digest(bytes));
+        Self { type_name, digest }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save(dedup: &mut Dedup<usize>, saved: &mut Vec<Vec<u8>>, saver: &impl SaveBlob) -> usize {
+        dedup.save_blob(saver, |bytes| -> Result<_, !> {
+            saved.push(bytes.to_vec());
+            Ok(saved.len() - 1)
+        }).into_ok()
+    }
+
+    #[test]
+    fn save_blob() {
+        let mut dedup = Dedup::new();
+        let mut saved = vec![];
+
+        assert_eq!(save(&mut dedup, &mut saved, &1u8), 0);
+        assert_eq!(save(&mut dedup, &mut saved, &2u8), 1);
+        assert_eq!(save(&mut dedup, &mut saved, &1u8), 0);
+
+        // Same bytes, different type.
+        assert_eq!(save(&mut dedup, &mut saved, &true), 2);
+        assert_eq!(save(&mut dedup, &mut saved, &true), 2);
+
+        assert_eq!(saved, &[vec![1], vec![2], vec![1]]);
+        assert_eq!(dedup.len(), 3);
+
+        // Failed saves aren't recorded.
+        assert_eq!(dedup.save_blob(&3u8, |_| Err(())), Err(()));
+        assert_eq!(save(&mut dedup, &mut saved, &3u8), 3);
+    }
+
+    #[test]
+    fn save_blob_pending() {
+        let mut dedup = Dedup::new();
+        let mut saved = vec![];
+        assert_eq!(save(&mut dedup, &mut saved, &1u8), 0);
+
+        let mut pending = Dedup::new();
+        let mut save_pending = |pending: &mut Dedup<usize>, saver: &u8| {
+            dedup.save_blob_pending(pending, saver, |bytes| -> Result<_, !> {
+                saved.push(bytes.to_vec());
+                Ok(saved.len() - 1)
+            }).into_ok()
+        };
+        assert_eq!(save_pending(&mut pending, &1), 0);
+        assert_eq!(save_pending(&mut pending, &2), 1);
+        assert_eq!(save_pending(&mut pending, &2), 1);
+        assert_eq!(pending.len(), 1);
+
+        assert_eq!(dedup.len(), 1);
+        dedup.extend(pending);
+        assert_eq!(dedup.len(), 2);
+        assert_eq!(saved, &[vec![1], vec![2]]);
+    }
+}
@@ -1,10 +1,19 @@
 use crate::pointee::Pointee;
+use crate::load::Load;
+use crate::ptr::Ptr;
+use crate::refs::Ref;
 
 pub mod blob;
 pub use self::blob::*;
 
 pub mod impls;
 
+pub mod dedup;
+pub use self::dedup::Dedup;
+
+pub mod vec;
+pub use self::vec::{to_vec, to_vec_dedup};
+
 pub trait Encode<Q, R> {
     type EncodePoll : SavePoll<Q, R> + EncodeBlob;
 
@@ -50,8 +59,19 @@ pub trait SavePtr : Sized {
     type Target;
     type Error;
 
-    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, &'a T>
-        where T: Pointee;
+    /// Checks whether `ptr` needs to be saved.
+    ///
+    /// Returns the existing target pointer if not. Otherwise returns the value, which is either
+    /// dirty, or has been loaded because the destination wants a fresh copy of it.
+    ///
+    /// Returning a `Ref` rather than a `&T` is what lets a destination walk clean values as if
+    /// they were dirty: journal compaction copies them this way. Hence the `T: Load` bound, here
+    /// and on the `Encode` impls of pointers such as `Own`; anything that can be loaded through a
+    /// pointer already meets it. The `T: Save` bound, which those impls meet too, lets a
+    /// destination walk the value later instead, as `pile::validate` does.
+    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Self::Source, metadata: T::Metadata) -> Result<Self::Target, Ref<'a, T>>
+        where T: Load<Self::Source> + Save<Self::Source, Self::Target>,
+              Self::Source: Ptr;
 
     fn try_save_ptr(self, saver: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error>;
 }
@@ -0,0 +1,175 @@
+//! Saving a whole tree to a self-contained buffer.
+
+use std::any::type_name;
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::offset::Offset;
+
+use super::*;
+
+/// Saves `root`, and everything reachable from it, to a new buffer.
+///
+/// Returns the buffer and the offset of `root` within it. Every pointer in the buffer is an
+/// offset into the buffer itself, so it can be opened with `Pile::new_unchecked()`, sent over a
+/// socket, or stored as a test fixture.
+///
+/// The offsets aren't branded with any pile; use `Pile::offset()` to get the root's offset in the
+/// pile the buffer is opened as.
+///
+/// # Panics
+///
+/// If a pointer reachable from `root` isn't dirty: it points into a zone that won't be part of
+/// the buffer.
+pub fn to_vec<T: ?Sized, Q: Ptr>(root: &T) -> (Vec<u8>, Offset<'static, 'static>)
+    where T: Save<Q, Offset<'static, 'static>>
+{
+    save_to_vec(root, VecSaver::default())
+}
+
+/// Saves `root` to a new buffer like `to_vec()`, saving identical blobs only once.
+///
+/// Blobs of the same type with the same bytes, such as repeated empty subtrees, share a single
+/// copy in the buffer. This costs a hash of every blob saved.
+pub fn to_vec_dedup<T: ?Sized, Q: Ptr>(root: &T) -> (Vec<u8>, Offset<'static, 'static>)
+    where T: Save<Q, Offset<'static, 'static>>
+{
+    let saver = VecSaver {
+        dedup: Some(Dedup::new()),
+        ..VecSaver::default()
+    };
+    save_to_vec(root, saver)
+}
+
+fn save_to_vec<T: ?Sized, Q: Ptr>(root: &T, saver: VecSaver<Q>) -> (Vec<u8>, Offset<'static, 'static>)
+    where T: Save<Q, Offset<'static, 'static>>
+{
+    let mut poll = root.init_save(&saver);
+    let saver = poll.save_poll(saver).into_ok();
+    let (saver, offset) = saver.try_save_ptr(&poll).into_ok();
+    (saver.buf, offset)
+}
+
+#[derive(Debug)]
+struct VecSaver<Q> {
+    marker: PhantomData<fn(Q)>,
+    buf: Vec<u8>,
+    dedup: Option<Dedup<Offset<'static, 'static>>>,
+}
+
+impl<Q> Default for VecSaver<Q> {
+    fn default() -> Self {
+        Self {
+            marker: PhantomData,
+            buf: vec![],
+            dedup: None,
+        }
+    }
+}
+
+impl<Q> SavePtr for VecSaver<Q> {
+    type Source = Q;
+    type Target = Offset<'static, 'static>;
+    type Error = !;
+
+    unsafe fn check_dirty<'a, T: ?Sized>(&self, ptr: &'a Q, metadata: T::Metadata) -> Result<Self::Target, Ref<'a, T>>
+        where T: Load<Q>,
+              Q: Ptr
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Err(Ref::Ref(r)),
+            Err(_) => panic!("to_vec(): clean `{}` pointer can't be saved", type_name::<T>()),
+        }
+    }
+
+    fn try_save_ptr(mut self, value: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        let offset = if let Some(dedup) = &mut self.dedup {
+            let buf = &mut self.buf;
+            dedup.save_blob(value, |bytes| {
+                let offset = Offset::new(buf.len()).expect("overflow");
+                buf.extend_from_slice(bytes);
+                Ok(offset)
+            })?
+        } else {
+            let offset = Offset::new(self.buf.len()).expect("overflow");
+            let buf = mem::replace(&mut self.buf, vec![]);
+            self.buf = value.save_blob(buf).into_ok();
+            offset
+        };
+        Ok((self, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::heap::HeapPtr;
+    use crate::offset::OffsetMut;
+    use crate::pile::TryPile;
+    use crate::ptr::Own;
+
+    #[test]
+    fn heap_tree() {
+        let root = [HeapPtr::alloc(Some(HeapPtr::alloc(1u8))),
+                    HeapPtr::alloc(None),
+                    HeapPtr::alloc(Some(HeapPtr::alloc(3u8)))];
+
+        let (buf, offset) = to_vec(&root);
+
+        // The same tree, with offsets in place of heap pointers
+        type Saved<'p, 'v> = [Own<Option<Own<u8, Offset<'p, 'v>>>, Offset<'p, 'v>>; 3];
+
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let validated = pile.validate_deep::<Saved>(pile.offset(offset.get()).unwrap()).unwrap();
+        let zone = validated.zone();
+        let saved = validated.get();
+
+        let leaves: Vec<Option<u8>> = saved.iter()
+            .map(|own| own.try_get_in(&zone).unwrap().as_ref().map(|leaf| *leaf.try_get_in(&zone).unwrap()))
+            .collect();
+        assert_eq!(leaves, &[Some(1), None, Some(3)]);
+    }
+
+    #[test]
+    fn offsetmut_tree() {
+        let root = OffsetMut::alloc(OffsetMut::alloc(42u8));
+        let (buf, offset) = to_vec(&root);
+        assert_eq!(offset, 9);
+        assert_eq!(buf, &[42, 1,0,0,0,0,0,0,0, 3,0,0,0,0,0,0,0]);
+    }
+
+    #[test]
+    fn dedup() {
+        let root = [HeapPtr::alloc(Some(HeapPtr::alloc(1u8))),
+                    HeapPtr::alloc(None),
+                    HeapPtr::alloc(Some(HeapPtr::alloc(1u8))),
+                    HeapPtr::alloc(None)];
+
+        let (buf, _) = to_vec(&root);
+        assert_eq!(buf.len(), 2 + 4 * 9 + 4 * 8);
+
+        // Both leaves, and the options pointing to them, are saved once.
+        let (buf, offset) = to_vec_dedup(&root);
+        assert_eq!(buf.len(), 1 + 2 * 9 + 4 * 8);
+
+        type Saved<'p, 'v> = [Own<Option<Own<u8, Offset<'p, 'v>>>, Offset<'p, 'v>>; 4];
+        let pile = unsafe { TryPile::new_unchecked(&buf) };
+        let validated = pile.validate_deep::<Saved>(pile.offset(offset.get()).unwrap()).unwrap();
+        assert_eq!(validated.blob_count(), 1 + 2 + 1);
+
+        let saved = validated.get();
+        let zone = validated.zone();
+        let leaves: Vec<Option<u8>> = saved.iter()
+            .map(|own| own.try_get_in(&zone).unwrap().as_ref().map(|leaf| *leaf.try_get_in(&zone).unwrap()))
+            .collect();
+        assert_eq!(leaves, &[Some(1), None, Some(1), None]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn clean_ptr() {
+        let own: Own<u8, OffsetMut> = unsafe { Own::new_unchecked(crate::ptr::Fat::new(Offset::new(0).unwrap().into(), ())) };
+        to_vec::<_, OffsetMut>(&own);
+    }
+}
@@ -26,17 +26,22 @@ impl From<Flags> for AtomicU8 {
 }
 
 #[derive(Debug, Error, PartialEq, Eq, PartialOrd, Ord)]
-#[error("invalid flags: {0}")]
-pub struct ValidateFlagsBlobError(u8);
+pub enum ValidateFlagsBlobError {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid flags: {0}")]
+    Invalid(u8),
+}
 
 impl ValidateBlob for Flags {
     type Error = ValidateFlagsBlobError;
     const BLOB_LEN: usize = mem::size_of::<Self>();
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        match blob.field_bytes(1)[0] {
-            0 => unsafe { Ok(blob.finish()) },
-            x => Err(ValidateFlagsBlobError(x)),
+        match blob.field::<u8, Self::Error>(|never| never)?.as_value() {
+            0 => unsafe { blob.finish() },
+            x => Err(ValidateFlagsBlobError::Invalid(*x)),
         }
     }
 }
@@ -72,7 +77,7 @@ mod tests {
         // having any flags set at all is invalid
         for i in 1 ..= 255 {
             assert_eq!(Flags::try_decode_blob_bytes(&[i]),
-                       Err(ValidateFlagsBlobError(i)));
+                       Err(DecodeBytesError::Validate(ValidateFlagsBlobError::Invalid(i))));
         }
 
         assert_eq!(Flags::empty().encode_blob_bytes(), &[0]);
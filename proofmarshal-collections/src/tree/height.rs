@@ -380,17 +380,22 @@ impl Verbatim for NonZeroHeight {
 }
 
 #[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
-#[error("out of range: {0}")]
-pub struct ValidateBlobHeightError(u8);
+pub enum ValidateBlobHeightError {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("out of range: {0}")]
+    OutOfRange(u8),
+}
 
 impl ValidateBlob for Height {
     type Error = ValidateBlobHeightError;
     const BLOB_LEN: usize = mem::size_of::<Self>();
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        match blob.field_bytes(1)[0] {
-            0 ..= Self::MAX => unsafe { Ok(blob.finish()) },
-            x => Err(ValidateBlobHeightError(x)),
+        match blob.field::<u8, Self::Error>(|never| never)?.as_value() {
+            0 ..= Self::MAX => unsafe { blob.finish() },
+            x => Err(ValidateBlobHeightError::OutOfRange(*x)),
         }
     }
 }
@@ -414,17 +419,22 @@ impl<Q, R> Encode<Q, R> for Height {
 impl Primitive for Height {}
 
 #[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
-#[error("out of range: {0}")]
-pub struct ValidateBlobNonZeroHeightError(u8);
+pub enum ValidateBlobNonZeroHeightError {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("out of range: {0}")]
+    OutOfRange(u8),
+}
 
 impl ValidateBlob for NonZeroHeight {
     type Error = ValidateBlobNonZeroHeightError;
     const BLOB_LEN: usize = mem::size_of::<Self>();
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        match blob.field_bytes(1)[0] {
-            1 ..= Self::MAX => unsafe { Ok(blob.finish()) },
-            x => Err(ValidateBlobNonZeroHeightError(x)),
+        match blob.field::<u8, Self::Error>(|never| never)?.as_value() {
+            1 ..= Self::MAX => unsafe { blob.finish() },
+            x => Err(ValidateBlobNonZeroHeightError::OutOfRange(*x)),
         }
     }
 }
@@ -463,7 +473,7 @@ mod tests {
                    63);
 
         assert_eq!(Height::try_decode_blob_bytes(&[Height::MAX + 1]).unwrap_err(),
-                   ValidateBlobHeightError(64));
+                   DecodeBytesError::Validate(ValidateBlobHeightError::OutOfRange(64)));
     }
 
     #[test]
@@ -478,9 +488,9 @@ mod tests {
                    63);
 
         assert_eq!(NonZeroHeight::try_decode_blob_bytes(&[0]).unwrap_err(),
-                   ValidateBlobNonZeroHeightError(0));
+                   DecodeBytesError::Validate(ValidateBlobNonZeroHeightError::OutOfRange(0)));
 
         assert_eq!(NonZeroHeight::try_decode_blob_bytes(&[64]).unwrap_err(),
-                   ValidateBlobNonZeroHeightError(64));
+                   DecodeBytesError::Validate(ValidateBlobNonZeroHeightError::OutOfRange(64)));
     }
 }
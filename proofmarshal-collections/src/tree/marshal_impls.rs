@@ -13,11 +13,18 @@ use hoard::ptr::{Ptr, AsPtr};
 use super::flags::ValidateFlagsBlobError;
 
 #[derive(Debug, Error)]
-#[error("FIXME")]
-pub enum ValidateSumTreeDataBlobError<SumError: Error, PtrError: Error> {
-    Flags(<Flags as ValidateBlob>::Error),
-    Sum(SumError),
-    Ptr(PtrError),
+pub enum ValidateSumTreeDataBlobError<SumError: Error + 'static, PtrError: Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid tree flags")]
+    Flags(#[source] <Flags as ValidateBlob>::Error),
+
+    #[error("invalid tree sum")]
+    Sum(#[source] SumError),
+
+    #[error("invalid tree pointer")]
+    Ptr(#[source] PtrError),
 }
 
 impl<T, S, P> ValidateBlob for SumTreeData<T, S, P>
@@ -32,11 +39,11 @@ where S: ValidateBlob,
     type Error = ValidateSumTreeDataBlobError<<S as ValidateBlob>::Error, <P as ValidateBlob>::Error>;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        blob.field::<Flags>().map_err(ValidateSumTreeDataBlobError::Flags)?;
-        blob.field::<Digest>().into_ok();
-        blob.field::<S>().map_err(ValidateSumTreeDataBlobError::Sum)?;
-        blob.field::<P>().map_err(ValidateSumTreeDataBlobError::Ptr)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<Flags, _>(ValidateSumTreeDataBlobError::Flags)?;
+        blob.field::<Digest, Self::Error>(|never| never)?;
+        blob.field::<S, _>(ValidateSumTreeDataBlobError::Sum)?;
+        blob.field::<P, _>(ValidateSumTreeDataBlobError::Ptr)?;
+        unsafe { blob.finish() }
     }
 }
 
@@ -61,11 +68,18 @@ unsafe impl<T, S, P> Persist for SumTreeData<T, S, P>
 where S: Persist, P: Persist {}
 
 #[derive(Debug, Error)]
-#[error("FIXME")]
-pub enum ValidateSumTreeBlobError<SumError: Error, PtrError: Error, ZoneError: Error> {
-    Data(ValidateSumTreeDataBlobError<SumError, PtrError>),
-    Zone(ZoneError),
-    Height(<Height as ValidateBlob>::Error),
+pub enum ValidateSumTreeBlobError<SumError: Error + 'static, PtrError: Error + 'static, ZoneError: Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid tree data")]
+    Data(#[source] ValidateSumTreeDataBlobError<SumError, PtrError>),
+
+    #[error("invalid tree zone")]
+    Zone(#[source] ZoneError),
+
+    #[error("invalid tree height")]
+    Height(#[source] <Height as ValidateBlob>::Error),
 }
 
 impl<T, S, P: Ptr, Z> ValidateBlob for SumTree<T, S, P, Z>
@@ -80,10 +94,10 @@ where S: ValidateBlob,
     type Error = ValidateSumTreeBlobError<S::Error, P::Error, Z::Error>;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        blob.field::<SumTreeData<T, S, P>>().map_err(ValidateSumTreeBlobError::Data)?;
-        blob.field::<Z>().map_err(ValidateSumTreeBlobError::Zone)?;
-        blob.field::<Height>().map_err(ValidateSumTreeBlobError::Height)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<SumTreeData<T, S, P>, _>(ValidateSumTreeBlobError::Data)?;
+        blob.field::<Z, _>(ValidateSumTreeBlobError::Zone)?;
+        blob.field::<Height, _>(ValidateSumTreeBlobError::Height)?;
+        unsafe { blob.finish() }
     }
 }
 
@@ -107,11 +121,18 @@ where S: Decode<Q>,
 }
 
 #[derive(Debug, Error)]
-#[error("FIXME")]
-pub enum ValidateInnerBlobError<SumError: Error, PtrError: Error> {
-    Left(ValidateSumTreeDataBlobError<SumError, PtrError>),
-    Right(ValidateSumTreeDataBlobError<SumError, PtrError>),
-    Height(<Height as ValidateBlob>::Error),
+pub enum ValidateInnerBlobError<SumError: Error + 'static, PtrError: Error + 'static> {
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error("invalid left child")]
+    Left(#[source] ValidateSumTreeDataBlobError<SumError, PtrError>),
+
+    #[error("invalid right child")]
+    Right(#[source] ValidateSumTreeDataBlobError<SumError, PtrError>),
+
+    #[error("invalid inner node height")]
+    Height(#[source] <Height as ValidateBlob>::Error),
 }
 
 impl<T, S, P: Ptr> ValidateBlob for Inner<T, S, P>
@@ -125,10 +146,10 @@ where S: ValidateBlob,
     type Error = ValidateInnerBlobError<S::Error, P::Error>;
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
-        blob.field::<SumTreeData<T, S, P>>().map_err(ValidateInnerBlobError::Left)?;
-        blob.field::<SumTreeData<T, S, P>>().map_err(ValidateInnerBlobError::Right)?;
-        blob.field::<Height>().map_err(ValidateInnerBlobError::Height)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<SumTreeData<T, S, P>, _>(ValidateInnerBlobError::Left)?;
+        blob.field::<SumTreeData<T, S, P>, _>(ValidateInnerBlobError::Right)?;
+        blob.field::<Height, _>(ValidateInnerBlobError::Height)?;
+        unsafe { blob.finish() }
     }
 }
 
@@ -161,10 +182,10 @@ where S: ValidateBlob,
 
     fn validate_blob<'a>(mut blob: BlobValidator<'a, Self>) -> Result<ValidBlob<'a, Self>, Self::Error> {
         /*
-        blob.field::<SumTreeData<T, S, P>>().map_err(ValidateInnerBlobError::Left)?;
-        blob.field::<SumTreeData<T, S, P>>().map_err(ValidateInnerBlobError::Right)?;
-        blob.field::<Height>().map_err(ValidateInnerBlobError::Height)?;
-        unsafe { Ok(blob.finish()) }
+        blob.field::<SumTreeData<T, S, P>, _>(ValidateInnerBlobError::Left)?;
+        blob.field::<SumTreeData<T, S, P>, _>(ValidateInnerBlobError::Right)?;
+        blob.field::<Height, _>(ValidateInnerBlobError::Height)?;
+        unsafe { blob.finish() }
         */ todo!()
     }
 }
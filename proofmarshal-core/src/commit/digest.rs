@@ -28,10 +28,13 @@ use std::hash;
 use std::marker::PhantomData;
 use std::mem;
 
+use owned::IntoOwned;
+
 use hoard::blob::*;
 use hoard::load::*;
 use hoard::save::*;
-use hoard::ptr::Ptr;
+use hoard::pointee::Pointee;
+use hoard::ptr::{AsPtr, Fat, Own, Ptr};
 use hoard::primitive::*;
 
 use super::*;
@@ -273,6 +276,48 @@ impl<T: ?Sized> EncodeBlob for Digest<T> {
 
 impl<T: ?Sized> Primitive for Digest<T> {}
 
+impl AsPtr<Self> for Digest {
+    #[inline(always)]
+    fn as_ptr(&self) -> &Self {
+        self
+    }
+}
+
+/// A digest is a pointer to the blob it's the hash of, in a content-addressed store.
+///
+/// See `commit::store`.
+impl Ptr for Digest {
+    type Persist = Self;
+    type PersistZone = ();
+
+    #[inline(always)]
+    unsafe fn dealloc<T: ?Sized + Pointee>(&self, _: T::Metadata) {
+        // nothing to do here
+    }
+
+    #[inline(always)]
+    fn duplicate(&self) -> Self {
+        *self
+    }
+
+    #[inline(always)]
+    unsafe fn clone_unchecked_with<T: ?Sized + Pointee, U, F>(&self, metadata: T::Metadata, _: F) -> Own<T, Self> {
+        Own::new_unchecked(Fat::new(*self, metadata))
+    }
+
+    #[inline(always)]
+    unsafe fn try_get_dirty_unchecked<T: ?Sized + Pointee>(&self, _: T::Metadata) -> Result<&T, Self::Persist> {
+        Err(*self)
+    }
+
+    #[inline(always)]
+    unsafe fn try_take_dirty_unchecked<T: ?Sized + Pointee>(self, _: T::Metadata) -> Result<T::Owned, Self::Persist>
+        where T: IntoOwned
+    {
+        Err(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -5,6 +5,9 @@ use hoard::pointee::Pointee;
 pub mod digest;
 pub use self::digest::Digest;
 
+pub mod store;
+pub use self::store::{Store, StoreZone};
+
 mod impls;
 
 pub trait Verbatim {
@@ -0,0 +1,394 @@
+//! Content-addressed storage.
+//!
+//! A `StoreZone` saves every blob under the digest of its bytes, so the persistent pointer to a
+//! value is just a `Digest`. The blobs themselves live in a `Store`: a directory, an in-memory
+//! `HashMap`, or something remote. Stores aren't trusted; every blob loaded is hashed, and
+//! rejected if it doesn't match the digest it was asked for.
+//!
+//! Blobs are also validated in canonical mode, so that a value has exactly one digest: a blob
+//! with non-zero padding is rejected even if its digest matches.
+//!
+//! Blobs are keyed by `blob_digest()`, a hash of their raw bytes, which is distinct from the
+//! `Commit::commit()` digest of the value they encode.
+
+use std::any::type_name;
+use std::collections::HashMap;
+use std::error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use sha2::Digest as _;
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+use hoard::blob::{Blob, BlobLen, BlobValidator};
+use hoard::load::{BlobDecoder, Load};
+use hoard::ptr::{AsPtr, Ptr, TryGet};
+use hoard::refs::Ref;
+use hoard::save::{Save, SaveBlob, SavePoll, SavePtr};
+
+use super::{CommitHasher, Digest, WriteVerbatim};
+
+/// A key-value store of blobs, keyed by digest.
+pub trait Store {
+    type Error : 'static + error::Error;
+
+    /// Returns the blob stored under `digest`, if any.
+    ///
+    /// The blob is returned as-is; it's up to the caller to check that it hashes to `digest`.
+    fn get_blob(&self, digest: &Digest) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Stores `blob` under its digest. Storing a blob that's already present does nothing.
+    fn put_blob(&mut self, digest: &Digest, blob: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl Store for HashMap<Digest, Vec<u8>> {
+    type Error = !;
+
+    fn get_blob(&self, digest: &Digest) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.get(digest).cloned())
+    }
+
+    fn put_blob(&mut self, digest: &Digest, blob: &[u8]) -> Result<(), Self::Error> {
+        self.entry(*digest).or_insert_with(|| blob.to_vec());
+        Ok(())
+    }
+}
+
+/// A directory, with one file per blob named after its digest in hex.
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+impl DirStore {
+    /// Opens the store in `dir`, creating the directory if it doesn't exist.
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_owned() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of the file the blob with `digest` is stored in.
+    pub fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.dir.join(digest.to_string())
+    }
+}
+
+impl Store for DirStore {
+    type Error = io::Error;
+
+    fn get_blob(&self, digest: &Digest) -> Result<Option<Vec<u8>>, Self::Error> {
+        match fs::read(self.blob_path(digest)) {
+            Ok(blob) => Ok(Some(blob)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn put_blob(&mut self, digest: &Digest, blob: &[u8]) -> Result<(), Self::Error> {
+        let path = self.blob_path(digest);
+        if !path.exists() {
+            // Synced, then renamed into place, so a crash can't leave a truncated blob behind. The
+            // temporary file's name is unique, so concurrent writers of a blob don't collide.
+            let mut tmp = NamedTempFile::new_in(&self.dir)?;
+            tmp.write_all(blob)?;
+            tmp.as_file().sync_all()?;
+            tmp.persist(&path).map_err(|err| err.error)?;
+
+            // The rename itself is only durable once the directory is synced.
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/// An error getting a blob from a `StoreZone`.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum GetError {
+    #[error("store error: {0}")]
+    Store(Box<dyn error::Error>),
+
+    #[error("blob {0} not found")]
+    NotFound(Digest),
+
+    #[error("blob {expected} hashes to {actual}")]
+    Corrupt {
+        expected: Digest,
+        actual: Digest,
+    },
+
+    #[error("`{type_name}` blob {digest} is {len} bytes, expected {blob_len}")]
+    Length {
+        type_name: &'static str,
+        digest: Digest,
+        len: usize,
+        blob_len: usize,
+    },
+
+    #[error("invalid layout for `{type_name}` blob {digest}: {err}")]
+    Layout {
+        type_name: &'static str,
+        digest: Digest,
+        err: Box<dyn error::Error>,
+    },
+
+    #[error("`{type_name}` blob {digest} failed validation: {err}")]
+    Validate {
+        type_name: &'static str,
+        digest: Digest,
+        err: Box<dyn error::Error>,
+    },
+}
+
+/// A zone of `Digest` pointers, to blobs in a `Store`.
+///
+/// Values are always loaded as `Ref::Owned`, and are decoded with `()` as their zone, so a value
+/// that embeds its zone, such as a `Bag`, can't be loaded from a `StoreZone`.
+#[derive(Debug, Default, Clone)]
+pub struct StoreZone<S> {
+    store: S,
+}
+
+impl<S: Store> StoreZone<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Saves `root`, and everything dirty reachable from it, returning the digest of `root`.
+    ///
+    /// Clean pointers are assumed to be to blobs already in the store, and are left as they are.
+    pub fn save<Q: Ptr, T: ?Sized>(&mut self, root: &T) -> Result<Digest<T>, S::Error>
+        where T: Save<Q, Digest>,
+              Q::Persist: AsPtr<Digest>,
+    {
+        let saver = StoreSaver {
+            marker: PhantomData,
+            store: &mut self.store,
+        };
+        let mut poll = root.init_save(&saver);
+        let saver = poll.save_poll(saver)?;
+        let (_, digest) = saver.try_save_ptr(&poll)?;
+        Ok(digest.cast())
+    }
+
+    /// Loads the value with `digest`.
+    pub fn load<T: ?Sized>(&self, digest: Digest<T>, metadata: T::Metadata) -> Result<T::Owned, GetError>
+        where T: Load<Digest>
+    {
+        self.load_blob::<T>(&digest.cast(), metadata)
+    }
+
+    fn load_blob<T: ?Sized>(&self, digest: &Digest, metadata: T::Metadata) -> Result<T::Owned, GetError>
+        where T: Load<Digest>
+    {
+        let type_name = type_name::<T>();
+        let digest = *digest;
+
+        let blob_len = T::try_blob_len(metadata)
+                         .map_err(|err| GetError::Layout { type_name, digest, err: err.into() })?;
+
+        let buf = self.store.get_blob(&digest)
+                            .map_err(|err| GetError::Store(err.into()))?
+                            .ok_or(GetError::NotFound(digest))?;
+
+        let actual = blob_digest(&buf);
+        if actual != digest {
+            return Err(GetError::Corrupt { expected: digest, actual });
+        } else if buf.len() != blob_len {
+            return Err(GetError::Length { type_name, digest, len: buf.len(), blob_len });
+        }
+
+        // The length was checked above
+        let blob = unsafe { Blob::<T>::new_unchecked(&buf, metadata) };
+        let blob = T::validate_blob_ptr(BlobValidator::new_canonical(blob))
+                     .map_err(|err| GetError::Validate { type_name, digest, err: err.into() })?;
+
+        Ok(T::load_blob(BlobDecoder::new(blob, &())))
+    }
+}
+
+/// The tag blob digests are domain-separated with.
+const BLOB_DIGEST_TAG: &[u8] = b"proofmarshal-core::commit::store::blob_digest";
+
+/// Returns the digest a blob is stored under.
+///
+/// This is a hash of the blob's raw bytes, *not* the `Commit::commit()` digest of the value the
+/// blob encodes. To keep the two from being confused, it's a tagged hash:
+/// `SHA256(SHA256(tag) || SHA256(tag) || blob)`.
+pub fn blob_digest(blob: &[u8]) -> Digest {
+    let tag = sha2::Sha256::digest(BLOB_DIGEST_TAG);
+
+    let mut hasher = CommitHasher::new();
+    hasher.write_bytes(&tag);
+    hasher.write_bytes(&tag);
+    hasher.write_bytes(blob);
+    hasher.finalize()
+}
+
+impl<S: Store> TryGet<Digest> for StoreZone<S> {
+    type Error = GetError;
+
+    unsafe fn try_get_unchecked<'a, T: ?Sized>(&self, ptr: &'a Digest, metadata: T::Metadata)
+        -> Result<Ref<'a, T>, Self::Error>
+        where T: Load<Digest>
+    {
+        self.load_blob::<T>(ptr, metadata).map(Ref::Owned)
+    }
+
+    unsafe fn try_take_unchecked<'a, T: ?Sized>(&self, ptr: Digest, metadata: T::Metadata)
+        -> Result<T::Owned, Self::Error>
+        where T: Load<Digest>
+    {
+        self.load_blob::<T>(&ptr, metadata)
+    }
+}
+
+/// Saves into a `Store`.
+#[derive(Debug)]
+struct StoreSaver<'a, Q, S> {
+    marker: PhantomData<fn(Q)>,
+    store: &'a mut S,
+}
+
+impl<'a, Q: Ptr, S: Store> SavePtr for StoreSaver<'a, Q, S>
+where Q::Persist: AsPtr<Digest>
+{
+    type Source = Q;
+    type Target = Digest;
+    type Error = S::Error;
+
+    unsafe fn check_dirty<'b, T: ?Sized>(&self, ptr: &'b Q, metadata: T::Metadata) -> Result<Self::Target, Ref<'b, T>>
+        where T: Load<Q>
+    {
+        match ptr.try_get_dirty_unchecked::<T>(metadata) {
+            Ok(r) => Err(Ref::Ref(r)),
+            Err(persist) => Ok(*persist.as_ptr()),
+        }
+    }
+
+    fn try_save_ptr(self, saver: &impl SaveBlob) -> Result<(Self, Self::Target), Self::Error> {
+        let blob = saver.save_blob(vec![]).into_ok();
+        let digest = blob_digest(&blob);
+        self.store.put_blob(&digest, &blob)?;
+        Ok((self, digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use hoard::Le;
+    use hoard::heap::HeapPtr;
+    use hoard::ptr::Own;
+
+    type Tree = [Own<Le<u32>, Digest>; 3];
+
+    fn save_tree<S: Store>(zone: &mut StoreZone<S>) -> Result<Digest<Tree>, S::Error> {
+        let root = [HeapPtr::alloc(Le::new(1u32)),
+                    HeapPtr::alloc(Le::new(2u32)),
+                    HeapPtr::alloc(Le::new(1u32))];
+        zone.save::<HeapPtr, _>(&root).map(|digest| digest.cast())
+    }
+
+    #[test]
+    fn hashmap_store() {
+        let mut zone = StoreZone::new(HashMap::new());
+        let digest = save_tree(&mut zone).into_ok();
+
+        // The two identical leaves share a blob.
+        assert_eq!(zone.store().len(), 3);
+
+        let root = zone.load(digest, ()).unwrap();
+        let leaves: Vec<u32> = root.iter().map(|leaf| leaf.try_get_in(&zone).unwrap().get()).collect();
+        assert_eq!(leaves, &[1, 2, 1]);
+
+        let err = zone.load(Digest::<Tree>::default(), ()).unwrap_err();
+        assert!(matches!(err, GetError::NotFound(_)));
+
+        let leaf: Digest<Le<u32>> = root[0].as_ref().raw.cast();
+        let err = zone.load(leaf.cast::<Le<u64>>(), ()).unwrap_err();
+        assert!(matches!(err, GetError::Length { len: 4, blob_len: 8, .. }));
+    }
+
+    #[test]
+    fn non_canonical_blob() {
+        let mut zone = StoreZone::new(HashMap::new());
+
+        // A `None` with non-zero padding.
+        let blob = [0, 0, 7, 0, 0];
+        let digest = blob_digest(&blob);
+        zone.store.put_blob(&digest, &blob).into_ok();
+
+        let err = zone.load(digest.cast::<Option<Le<u32>>>(), ()).unwrap_err();
+        assert!(matches!(err, GetError::Validate { .. }));
+        assert!(err.to_string().ends_with("non-zero padding at bytes 2..3"), "{}", err);
+
+        let blob = [0; 5];
+        let digest = blob_digest(&blob);
+        zone.store.put_blob(&digest, &blob).into_ok();
+        assert_eq!(zone.load(digest.cast::<Option<Le<u32>>>(), ()).unwrap(), None);
+    }
+
+    #[test]
+    fn dir_store() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut zone = StoreZone::new(DirStore::new(dir.path())?);
+        let digest = save_tree(&mut zone)?;
+        assert_eq!(fs::read_dir(dir.path())?.count(), 3);
+
+        let zone = StoreZone::new(DirStore::new(dir.path())?);
+        let root = zone.load(digest, ()).unwrap();
+        let leaf = root[1].try_get_in(&zone).unwrap();
+        assert_eq!(leaf.get(), 2);
+
+        // Tampering with a blob is detected.
+        let leaf_path = zone.store().blob_path(&root[1].as_ref().raw);
+        fs::write(&leaf_path, &[3, 0, 0, 0])?;
+        let err = root[1].try_get_in(&zone).unwrap_err();
+        assert!(matches!(err, GetError::Corrupt { .. }));
+
+        // A new blob is written through a temporary file, which doesn't outlive the write.
+        let mut other = DirStore::new(dir.path())?;
+        let blob = [9, 9, 9, 9];
+        let blob_path = other.blob_path(&blob_digest(&blob));
+        assert!(!blob_path.exists());
+        other.put_blob(&blob_digest(&blob), &blob)?;
+        assert_eq!(fs::read(&blob_path)?, &blob);
+
+        let names = fs::read_dir(dir.path())?
+                        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                        .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(names.len(), 4);
+        assert!(names.iter().all(|name| !name.starts_with(".tmp")), "{:?}", names);
+        Ok(())
+    }
+
+    #[test]
+    fn blob_digest_domain() {
+        let blob = [1, 0, 0, 0];
+
+        let mut hasher = CommitHasher::new();
+        hasher.write_bytes(&blob);
+        assert_ne!(blob_digest(&blob), hasher.finalize());
+        assert_eq!(blob_digest(&blob), blob_digest(&blob));
+    }
+}
@@ -1,6 +1,7 @@
 //! Marshalling of merkelized cryptographic proofs - what the cool kids call "blockchain".
 
 #![feature(never_type)]
+#![feature(unwrap_infallible)]
 #![feature(rustc_attrs)]
 
 #![allow(incomplete_features)]
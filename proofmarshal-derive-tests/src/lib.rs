@@ -19,6 +19,13 @@ pub enum Node {
     }
 }
 
+#[derive(Commit)]
+pub enum Padded {
+    Short(u8),
+    Empty,
+    Long(Le<u32>),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -33,7 +40,7 @@ mod tests {
         };
 
         assert_eq!(
-            outpoint.encode_verbatim(vec![]).unwrap(),
+            outpoint.encode_verbatim(),
             &[22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 11, 0, 0, 0][..]
         );
     }
@@ -42,12 +49,21 @@ mod tests {
     fn test_enum() {
         assert_eq!(Node::LEN, 3);
         assert_eq!(
-            Node::Leaf(11).encode_verbatim(vec![]).unwrap(),
+            Node::Leaf(11).encode_verbatim(),
             &[0, 11, 0][..]
         );
         assert_eq!(
-            Node::Inner { left: 13, right: 14 }.encode_verbatim(vec![]).unwrap(),
+            Node::Inner { left: 13, right: 14 }.encode_verbatim(),
             &[1, 13, 14][..]
         );
     }
+
+    #[test]
+    fn test_enum_padding() {
+        // Shorter variants are padded with zeros, never anything else.
+        assert_eq!(Padded::LEN, 5);
+        assert_eq!(Padded::Short(7).encode_verbatim(), &[0, 7, 0, 0, 0][..]);
+        assert_eq!(Padded::Empty.encode_verbatim(), &[1, 0, 0, 0, 0][..]);
+        assert_eq!(Padded::Long(Le::new(0xffff_ffff)).encode_verbatim(), &[2, 0xff, 0xff, 0xff, 0xff][..]);
+    }
 }
@@ -17,7 +17,7 @@ pub fn derive_commit_for_struct(s: synstructure::Structure) -> proc_macro2::Toke
         field_lens.push(quote! { <#ty as ::proofmarshal_core::commit::Verbatim>::LEN });
 
         quote! {
-            __dst = __dst.write(#bi)?;
+            __dst.write(#bi);
         }
     });
 
@@ -25,14 +25,10 @@ pub fn derive_commit_for_struct(s: synstructure::Structure) -> proc_macro2::Toke
         gen impl ::proofmarshal_core::commit::Verbatim for @Self {
             const LEN: usize = 0 #( + #field_lens )*;
 
-            fn encode_verbatim<__W>(&self, mut __dst: __W) -> Result<__W, __W::Error>
-                where __W: ::proofmarshal_core::commit::WriteVerbatim
-            {
+            fn encode_verbatim_in(&self, __dst: &mut impl ::proofmarshal_core::commit::WriteVerbatim) {
                 match self {
                     #body
                 };
-
-                __dst.finish()
             }
         }
     });
@@ -46,7 +42,7 @@ pub fn derive_commit_for_enum(s: synstructure::Structure) -> proc_macro2::TokenS
         let pat = vi.pat();
         let fields = vi.bindings().iter().map(|bi| {
             quote! {
-                __dst = __dst.write(#bi)?;
+                __dst.write(#bi);
             }
         });
 
@@ -63,12 +59,12 @@ pub fn derive_commit_for_enum(s: synstructure::Structure) -> proc_macro2::TokenS
         let idx = u8::try_from(idx).expect("enums with > 255 variants not supported");
         quote! {
             #pat => {
-                __dst = __dst.write_bytes(&[#idx])?;
+                __dst.write_bytes(&[#idx]);
 
                 #( #fields )*
 
-                __dst = __dst.write_padding(Self::LEN - 1 - (#variant_len))?;
-                __dst.finish()
+                // Always zeros, the same as the padding a canonical blob is required to have.
+                __dst.write_zeros(Self::LEN - 1 - (#variant_len));
             },
         }
     }).collect::<Vec<_>>();
@@ -87,9 +83,7 @@ pub fn derive_commit_for_enum(s: synstructure::Structure) -> proc_macro2::TokenS
                 r
             };
 
-            fn encode_verbatim<__W>(&self, mut __dst: __W) -> Result<__W, __W::Error>
-                where __W: ::proofmarshal_core::commit::WriteVerbatim
-            {
+            fn encode_verbatim_in(&self, __dst: &mut impl ::proofmarshal_core::commit::WriteVerbatim) {
                 match self {
                     #( #variants )*
                 }